@@ -0,0 +1,313 @@
+use crate::node::*;
+use crate::noise::Rng;
+use crate::note::midi_name;
+use crate::tuning::Tuning;
+use eframe::egui::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Lowest note of the keyboard in the node.
+const KEYBOARD_START: i32 = 48;
+const KEYBOARD_KEYS: i32 = 24;
+
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum ArpOrder {
+    Up,
+    Down,
+    UpDown,
+    Random,
+    AsPlayed,
+}
+
+/// Plays the held notes one at a time at a rate synced to the transport.
+///
+/// Notes are held from the keyboard in the node or by the `freq` and `gate`
+/// inputs, which hold a note while the gate is open. In latch mode notes from
+/// the inputs stay held after the gate closes, until played again.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ArpeggiatorNode {
+    /// Notes held from the keyboard, in the order they were pressed.
+    keys: Vec<i32>,
+    order: ArpOrder,
+    octaves: u32,
+    /// Notes per beat.
+    rate: f64,
+    /// Fraction of a note the gate stays open.
+    gate_length: f64,
+    latch: bool,
+    /// Notes held from the inputs.
+    #[serde(skip)]
+    played: Vec<i32>,
+    #[serde(skip)]
+    last_gate: f64,
+    /// Position in notes, only used while the transport is stopped.
+    #[serde(skip)]
+    position: f64,
+    #[serde(skip)]
+    last_step: Option<u64>,
+    #[serde(skip)]
+    note: Option<i32>,
+    /// The same seed always gives the same random order.
+    #[serde(default = "default_seed")]
+    seed: u64,
+    /// Started from `seed` on the first random note.
+    #[serde(skip)]
+    rng: Option<Rng>,
+}
+
+fn default_seed() -> u64 {
+    1
+}
+
+impl ArpeggiatorNode {
+    pub fn new() -> Self {
+        Self {
+            keys: Vec::new(),
+            order: ArpOrder::Up,
+            octaves: 1,
+            rate: 4.0,
+            gate_length: 0.5,
+            latch: false,
+            played: Vec::new(),
+            last_gate: 0.0,
+            position: 0.0,
+            last_step: None,
+            note: None,
+            seed: default_seed(),
+            rng: None,
+        }
+    }
+
+    /// The held notes in order of pressing, without duplicates.
+    fn held(&self) -> Vec<i32> {
+        let mut held: Vec<i32> = Vec::new();
+
+        for note in self.keys.iter().chain(&self.played) {
+            if !held.contains(note) {
+                held.push(*note);
+            }
+        }
+
+        held
+    }
+
+    /// One cycle of the arpeggio.
+    fn pattern(&self) -> Vec<i32> {
+        let mut held = self.held();
+
+        if self.order != ArpOrder::AsPlayed {
+            held.sort_unstable();
+        }
+
+        let mut pattern: Vec<i32> = (0..self.octaves.max(1) as i32)
+            .flat_map(|octave| held.iter().map(move |note| note + octave * 12))
+            .collect();
+
+        match self.order {
+            ArpOrder::Down => pattern.reverse(),
+            // turn around without repeating the highest and lowest notes
+            ArpOrder::UpDown if pattern.len() > 2 => {
+                let down: Vec<i32> = pattern[1..pattern.len() - 1]
+                    .iter()
+                    .rev()
+                    .cloned()
+                    .collect();
+                pattern.extend(down);
+            }
+            _ => {}
+        }
+
+        pattern
+    }
+
+    fn random(&mut self) -> u64 {
+        let seed = self.seed;
+
+        self.rng.get_or_insert_with(|| Rng::new(seed)).next_u64()
+    }
+
+    fn input_note(&mut self, tuning: &Tuning, freq: f64, gate: f64) {
+        let note = tuning.freq_to_note(freq).round() as i32;
+
+        if gate > 0.5 && self.last_gate <= 0.5 {
+            if self.latch && self.played.contains(&note) {
+                self.played.retain(|played| *played != note);
+            } else {
+                self.played.push(note);
+            }
+        } else if gate <= 0.5 && self.last_gate > 0.5 && !self.latch {
+            self.played.clear();
+        }
+
+        self.last_gate = gate;
+    }
+
+    fn keyboard_ui(&mut self, ui: &mut Ui) -> bool {
+        let mut changed = false;
+
+        for start in (0..KEYBOARD_KEYS).step_by(12) {
+            ui.horizontal(|ui| {
+                for note in KEYBOARD_START + start..KEYBOARD_START + start + 12 {
+                    let held = self.keys.contains(&note);
+
+                    if ui.selectable_label(held, midi_name(note)).clicked() {
+                        if held {
+                            self.keys.retain(|key| *key != note);
+                        } else {
+                            self.keys.push(note);
+                        }
+
+                        changed = true;
+                    }
+                }
+            });
+        }
+
+        changed
+    }
+}
+
+impl Node for ArpeggiatorNode {
+    fn name(&self) -> &str {
+        "Arpeggiator"
+    }
+
+    fn input_slot_types(&self) -> &[(&'static str, SlotType)] {
+        &[("freq", SlotType::Pitch), ("gate", SlotType::Gate)]
+    }
+
+    fn output_slot_types(&self) -> &[(&'static str, SlotType)] {
+        &[("freq", SlotType::Pitch), ("gate", SlotType::Gate)]
+    }
+
+    fn display_out(&self) -> &Option<&str> {
+        &Some("gate")
+    }
+
+    fn setup(&mut self) {
+        self.played.clear();
+        self.last_gate = 0.0;
+        self.position = 0.0;
+        self.last_step = None;
+        self.note = None;
+        self.rng = None;
+    }
+
+    fn run(
+        &mut self,
+        ctx: &NodeCtx,
+        input: HashMap<String, SlotValue>,
+    ) -> Vec<(&'static str, SlotValue)> {
+        if let SlotValue::Float(freq) = input["freq"] {
+            let gate = input["gate"].unwrap_f64(0.0);
+            self.input_note(ctx.tuning, freq, gate);
+        }
+
+        let pattern = self.pattern();
+
+        if pattern.is_empty() {
+            self.position = 0.0;
+            self.last_step = None;
+
+            return vec![
+                (
+                    "freq",
+                    self.note.map_or(SlotValue::None, |note| {
+                        SlotValue::Float(ctx.tuning.key_freq(note))
+                    }),
+                ),
+                ("gate", SlotValue::Float(0.0)),
+            ];
+        }
+
+        // locked to the transport while it plays, free running at its tempo
+        // otherwise
+        let position = if ctx.transport.playing {
+            ctx.transport.beats.max(0.0) * self.rate
+        } else {
+            self.position += ctx.sample_length * ctx.transport.bpm / 60.0 * self.rate;
+            self.position
+        };
+
+        let step = position.floor() as u64;
+
+        if self.last_step != Some(step) {
+            let index = match self.order {
+                ArpOrder::Random => self.random() as usize,
+                _ => step as usize,
+            };
+
+            self.note = Some(pattern[index % pattern.len()]);
+            self.last_step = Some(step);
+        }
+
+        let gate = position.fract() < self.gate_length;
+        let note = self.note.unwrap_or(pattern[0]);
+
+        vec![
+            ("freq", SlotValue::Float(ctx.tuning.key_freq(note))),
+            ("gate", SlotValue::Float(if gate { 1.0 } else { 0.0 })),
+        ]
+    }
+
+    fn ui(&mut self, ui: &mut Ui) -> bool {
+        let prev_order = self.order;
+        let prev_octaves = self.octaves;
+        let prev_latch = self.latch;
+        let mut changed = false;
+
+        ui.vertical(|ui| {
+            ui.horizontal(|ui| {
+                ui.radio_value(&mut self.order, ArpOrder::Up, "Up");
+                ui.radio_value(&mut self.order, ArpOrder::Down, "Down");
+                ui.radio_value(&mut self.order, ArpOrder::UpDown, "Up/down");
+                ui.radio_value(&mut self.order, ArpOrder::Random, "Random");
+                ui.radio_value(&mut self.order, ArpOrder::AsPlayed, "As played");
+            });
+
+            ui.horizontal(|ui| {
+                let mut octaves = self.octaves as f64;
+
+                ui.label("Octaves");
+                ui.add(DragValue::f64(&mut octaves).speed(0.05));
+
+                self.octaves = (octaves as u32).max(1).min(4);
+
+                ui.label("Notes per beat");
+                ui.add(DragValue::f64(&mut self.rate).speed(0.05));
+            });
+
+            ui.horizontal(|ui| {
+                ui.label("Gate");
+                ui.add(DragValue::f64(&mut self.gate_length).speed(0.01));
+                ui.checkbox(&mut self.latch, "Latch");
+            });
+
+            changed = self.keyboard_ui(ui);
+        });
+
+        self.rate = self.rate.max(0.25).min(16.0);
+        self.gate_length = self.gate_length.max(0.0).min(1.0);
+
+        if self.latch != prev_latch && !self.latch {
+            self.played.clear();
+        }
+
+        changed
+            || self.order != prev_order
+            || self.octaves != prev_octaves
+            || self.latch != prev_latch
+    }
+
+    fn params(&self) -> Vec<f64> {
+        vec![self.rate, self.gate_length]
+    }
+
+    fn set_param(&mut self, index: usize, value: f64) {
+        match index {
+            0 => self.rate = value,
+            1 => self.gate_length = value,
+            _ => {}
+        }
+    }
+}
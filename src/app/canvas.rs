@@ -0,0 +1,298 @@
+use crate::node::*;
+use eframe::egui::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+pub const GRID_SIZE: f32 = 20.0;
+pub const MIN_ZOOM: f32 = 0.2;
+pub const MAX_ZOOM: f32 = 2.0;
+
+/// Below this zoom level nodes only show their name and slots.
+pub const COLLAPSE_ZOOM: f32 = 0.6;
+
+const COLUMN_WIDTH: f32 = 300.0;
+const ROW_MARGIN: f32 = 40.0;
+const FRAME_MARGIN: f32 = 40.0;
+
+/// Pan and zoom of the node editor. Node positions are stored in canvas
+/// space and mapped to the screen through this.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CanvasView {
+    pub pan: Vec2,
+    pub zoom: f32,
+    pub snap: bool,
+    pub animate: bool,
+    #[serde(skip)]
+    pub viewport: Vec2,
+    #[serde(skip)]
+    pub node_sizes: HashMap<NodeId, Vec2>,
+}
+
+impl Default for CanvasView {
+    fn default() -> Self {
+        Self {
+            pan: Vec2::ZERO,
+            zoom: 1.0,
+            snap: true,
+            animate: true,
+            viewport: Vec2::ZERO,
+            node_sizes: HashMap::new(),
+        }
+    }
+}
+
+impl CanvasView {
+    pub fn to_screen(&self, origin: Pos2, pos: Pos2) -> Pos2 {
+        origin + (pos.to_vec2() + self.pan) * self.zoom
+    }
+
+    pub fn to_canvas(&self, origin: Pos2, pos: Pos2) -> Pos2 {
+        ((pos - origin) / self.zoom - self.pan).to_pos2()
+    }
+
+    pub fn collapsed(&self) -> bool {
+        self.zoom < COLLAPSE_ZOOM
+    }
+
+    pub fn snap(&self, pos: Pos2) -> Pos2 {
+        if self.snap {
+            Pos2::new(
+                (pos.x / GRID_SIZE).round() * GRID_SIZE,
+                (pos.y / GRID_SIZE).round() * GRID_SIZE,
+            )
+        } else {
+            pos
+        }
+    }
+
+    /// Zooms by `factor` while keeping the canvas point under `pointer` in place.
+    pub fn zoom_around(&mut self, origin: Pos2, pointer: Pos2, factor: f32) {
+        let before = self.to_canvas(origin, pointer);
+
+        self.zoom = (self.zoom * factor).max(MIN_ZOOM).min(MAX_ZOOM);
+        self.pan = (pointer - origin) / self.zoom - before.to_vec2();
+    }
+
+    /// Handles panning and zooming through the background of the canvas.
+    pub fn interact(&mut self, ui: &Ui, canvas: &Response) {
+        self.viewport = canvas.rect.size();
+
+        if canvas.dragged() {
+            self.pan += ui.input().pointer.delta() / self.zoom;
+        }
+
+        if canvas.hovered() {
+            let scroll = ui.input().scroll_delta.y;
+
+            if scroll != 0.0 {
+                if let Some(pointer) = ui.input().pointer.hover_pos() {
+                    let factor = (scroll * 0.002).exp();
+
+                    self.zoom_around(canvas.rect.min, pointer, factor);
+                }
+            }
+        }
+    }
+
+    pub fn draw_grid(&self, ui: &Ui, rect: Rect) {
+        let step = GRID_SIZE * self.zoom * if self.collapsed() { 4.0 } else { 1.0 };
+        let color = ui.style().visuals.faint_bg_color;
+        let stroke = Stroke::new(1.0, color);
+
+        let offset = self.to_screen(rect.min, Pos2::ZERO) - rect.min;
+
+        let mut x = rect.min.x + offset.x.rem_euclid(step);
+
+        while x < rect.max.x {
+            ui.painter()
+                .line_segment([Pos2::new(x, rect.min.y), Pos2::new(x, rect.max.y)], stroke);
+            x += step;
+        }
+
+        let mut y = rect.min.y + offset.y.rem_euclid(step);
+
+        while y < rect.max.y {
+            ui.painter()
+                .line_segment([Pos2::new(rect.min.x, y), Pos2::new(rect.max.x, y)], stroke);
+            y += step;
+        }
+    }
+}
+
+impl NodeManager {
+    /// True if every node sits at the same spot, as in patches saved before
+    /// node positions were stored.
+    pub fn needs_layout(&self) -> bool {
+        let mut positions = self.nodes.values().map(|node| node.position);
+
+        match positions.next() {
+            Some(first) => positions.all(|pos| pos == first),
+            None => false,
+        }
+    }
+
+    /// Arranges the nodes in columns by their distance from the output node,
+    /// with nodes that don't reach the output placed in a column of their own.
+    pub fn auto_layout(&mut self) {
+        let mut depths: HashMap<NodeId, usize> = HashMap::new();
+        let mut stack = vec![(self.output_node, 0)];
+
+        while let Some((id, depth)) = stack.pop() {
+            if depths.get(&id).map_or(false, |d| *d >= depth) {
+                continue;
+            }
+
+            depths.insert(id, depth);
+
+            if let Some(node) = self.nodes.get(&id) {
+                for (o_id, _) in node.connections.values() {
+                    stack.push((*o_id, depth + 1));
+                }
+            }
+        }
+
+        let max_depth = depths.values().cloned().max().unwrap_or(0);
+
+        let mut ids = self.nodes.keys().cloned().collect::<Vec<_>>();
+        ids.sort_by_key(|id| id.0);
+
+        let mut columns: Vec<Vec<NodeId>> = vec![Vec::new(); max_depth + 2];
+
+        for id in ids {
+            match depths.get(&id) {
+                Some(depth) => columns[max_depth - depth + 1].push(id),
+                None => columns[0].push(id),
+            }
+        }
+
+        for (x, column) in columns.iter().filter(|c| !c.is_empty()).enumerate() {
+            let mut y = 0.0;
+
+            for id in column {
+                let height = self
+                    .view
+                    .node_sizes
+                    .get(id)
+                    .map_or(150.0, |size| size.y / self.view.zoom);
+
+                let position = self.view.snap(Pos2::new(x as f32 * COLUMN_WIDTH, y));
+                self.nodes.get_mut(id).unwrap().position = position;
+
+                y += height + ROW_MARGIN;
+            }
+        }
+    }
+
+    /// Adds `node` in the middle of the last known viewport.
+    pub fn add_at_center(&mut self, mut node: NodeContainer) -> NodeId {
+        let center = (self.view.viewport * 0.5 / self.view.zoom - self.view.pan).to_pos2();
+        node.position = self.view.snap(center);
+
+        self.add(node)
+    }
+
+    /// Pans and zooms so every node fits in the last known viewport.
+    pub fn frame_all(&mut self) {
+        if self.nodes.is_empty() || self.view.viewport == Vec2::ZERO {
+            return;
+        }
+
+        let mut min = Pos2::new(f32::INFINITY, f32::INFINITY);
+        let mut max = Pos2::new(f32::NEG_INFINITY, f32::NEG_INFINITY);
+        let mut largest = Vec2::ZERO;
+
+        for (id, node) in &self.nodes {
+            min = min.min(node.position);
+            max = max.max(node.position);
+
+            if let Some(size) = self.view.node_sizes.get(id) {
+                largest = largest.max(*size);
+            }
+        }
+
+        let span = (max - min).max(Vec2::splat(1.0));
+        let available =
+            (self.view.viewport - largest - Vec2::splat(FRAME_MARGIN * 2.0)).max(Vec2::splat(1.0));
+
+        self.view.zoom = (available.x / span.x)
+            .min(available.y / span.y)
+            .max(MIN_ZOOM)
+            .min(MAX_ZOOM);
+        self.view.pan = Vec2::splat(FRAME_MARGIN) / self.view.zoom - min.to_vec2();
+    }
+}
+
+pub const WIRE_HOVER_DISTANCE: f32 = 6.0;
+
+const WIRE_SEGMENTS: usize = 24;
+const ACTIVITY_DOTS: usize = 3;
+const ACTIVITY_SPEED: f64 = 0.5;
+
+/// Points along the curve of a wire going from an output slot to an input slot.
+pub fn wire_points(from: Pos2, to: Pos2) -> Vec<Pos2> {
+    let bend = ((to.x - from.x).abs() * 0.5).max(40.0);
+
+    let c1 = from + Vec2::new(bend, 0.0);
+    let c2 = to - Vec2::new(bend, 0.0);
+
+    (0..=WIRE_SEGMENTS)
+        .map(|i| {
+            let t = i as f32 / WIRE_SEGMENTS as f32;
+            let u = 1.0 - t;
+
+            (from.to_vec2() * u * u * u
+                + c1.to_vec2() * 3.0 * u * u * t
+                + c2.to_vec2() * 3.0 * u * t * t
+                + to.to_vec2() * t * t * t)
+                .to_pos2()
+        })
+        .collect()
+}
+
+pub fn draw_wire(painter: &Painter, points: &[Pos2], stroke: Stroke) {
+    for pair in points.windows(2) {
+        painter.line_segment([pair[0], pair[1]], stroke);
+    }
+}
+
+/// Draws dots travelling along the wire, sized by the signal level on it.
+pub fn draw_activity(painter: &Painter, points: &[Pos2], color: Color32, time: f64, activity: f32) {
+    if activity <= 0.0 || points.len() < 2 {
+        return;
+    }
+
+    let radius = 1.5 + activity.min(1.0) * 2.0;
+
+    for i in 0..ACTIVITY_DOTS {
+        let t = (time * ACTIVITY_SPEED + i as f64 / ACTIVITY_DOTS as f64) % 1.0;
+        let position = t as f32 * (points.len() - 1) as f32;
+
+        let index = position as usize;
+        let a = points[index];
+        let b = points[(index + 1).min(points.len() - 1)];
+
+        painter.circle_filled(a + (b - a) * position.fract(), radius, color);
+    }
+}
+
+pub fn distance_to_wire(point: Pos2, points: &[Pos2]) -> f32 {
+    points
+        .windows(2)
+        .map(|pair| distance_to_segment(point, pair[0], pair[1]))
+        .fold(f32::INFINITY, f32::min)
+}
+
+fn distance_to_segment(point: Pos2, a: Pos2, b: Pos2) -> f32 {
+    let ab = b - a;
+    let length_sq = ab.x * ab.x + ab.y * ab.y;
+
+    if length_sq == 0.0 {
+        return point.distance(a);
+    }
+
+    let ap = point - a;
+    let t = ((ap.x * ab.x + ap.y * ab.y) / length_sq).max(0.0).min(1.0);
+
+    point.distance(a + ab * t)
+}
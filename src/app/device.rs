@@ -0,0 +1,273 @@
+use crate::input::*;
+use cpal::{traits::*, *};
+use eframe::egui::*;
+use serde::{Deserialize, Serialize};
+
+#[cfg(not(target_arch = "wasm32"))]
+use crate::driver::Driver;
+#[cfg(not(target_arch = "wasm32"))]
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::{Duration, Instant};
+
+/// Device name that runs the graph without any audio output.
+pub const NULL_DEVICE: &str = "Null device";
+
+const SAMPLE_RATES: [u32; 6] = [22050, 44100, 48000, 88200, 96000, 192000];
+
+/// The output stream to open, `None` means the default of the host or device.
+#[derive(Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AudioSettings {
+    pub host: Option<String>,
+    pub device: Option<String>,
+    pub sample_rate: Option<u32>,
+    pub buffer_size: Option<u32>,
+    pub input: InputSource,
+}
+
+pub fn find_host(settings: &AudioSettings) -> Host {
+    available_hosts()
+        .into_iter()
+        .find(|id| Some(id.name()) == settings.host.as_deref())
+        .and_then(|id| host_from_id(id).ok())
+        .unwrap_or_else(default_host)
+}
+
+pub fn find_output_device(settings: &AudioSettings) -> Result<Device, anyhow::Error> {
+    let host = find_host(settings);
+
+    let device = match &settings.device {
+        Some(name) => host
+            .output_devices()?
+            .find(|device| device.name().ok().as_ref() == Some(name)),
+        None => host.default_output_device(),
+    };
+
+    device.ok_or_else(|| anyhow::anyhow!("Output device not found"))
+}
+
+/// The default config of `device` with the sample rate and buffer size of
+/// `settings`, where they are supported.
+pub fn stream_config(
+    device: &Device,
+    settings: &AudioSettings,
+) -> Result<(SampleFormat, StreamConfig), anyhow::Error> {
+    let mut supported = device.default_output_config()?;
+
+    if let Some(rate) = settings.sample_rate {
+        let channels = supported.channels();
+
+        let range = device.supported_output_configs()?.find(|range| {
+            range.channels() == channels
+                && range.min_sample_rate().0 <= rate
+                && range.max_sample_rate().0 >= rate
+        });
+
+        if let Some(range) = range {
+            supported = range.with_sample_rate(SampleRate(rate));
+        }
+    }
+
+    let mut config = supported.config();
+
+    if let Some(size) = settings.buffer_size {
+        let size = match supported.buffer_size() {
+            SupportedBufferSize::Range { min, max } => size.max(*min).min(*max),
+            SupportedBufferSize::Unknown => size,
+        };
+
+        config.buffer_size = BufferSize::Fixed(size);
+    }
+
+    Ok((supported.sample_format(), config))
+}
+
+/// Hosts and devices found by the last scan, enumerating them is too slow
+/// to do every frame.
+pub struct DeviceList {
+    pub hosts: Vec<String>,
+    pub devices: Vec<String>,
+    pub input_devices: Vec<String>,
+    /// Common sample rates supported by the selected device.
+    pub sample_rates: Vec<u32>,
+}
+
+impl DeviceList {
+    pub fn scan(settings: &AudioSettings) -> Self {
+        let host = find_host(settings);
+
+        let devices = host
+            .output_devices()
+            .map(|devices| devices.filter_map(|device| device.name().ok()).collect())
+            .unwrap_or_default();
+
+        let input_devices = host
+            .input_devices()
+            .map(|devices| devices.filter_map(|device| device.name().ok()).collect())
+            .unwrap_or_default();
+
+        let sample_rates = find_output_device(settings)
+            .and_then(|device| Ok(device.supported_output_configs()?.collect::<Vec<_>>()))
+            .map(|ranges| {
+                SAMPLE_RATES
+                    .iter()
+                    .cloned()
+                    .filter(|rate| {
+                        ranges.iter().any(|range| {
+                            range.min_sample_rate().0 <= *rate && range.max_sample_rate().0 >= *rate
+                        })
+                    })
+                    .collect()
+            })
+            .unwrap_or_else(|_| SAMPLE_RATES.to_vec());
+
+        Self {
+            hosts: available_hosts()
+                .into_iter()
+                .map(|id| id.name().to_string())
+                .collect(),
+            devices,
+            input_devices,
+            sample_rates,
+        }
+    }
+}
+
+impl AudioSettings {
+    /// Shows the settings, returns true if they should be applied.
+    pub fn ui(&mut self, ui: &mut Ui, devices: &mut DeviceList) -> bool {
+        let prev = self.clone();
+
+        ui.label("Host");
+        ui.radio_value(&mut self.host, None, "Default");
+
+        for host in &devices.hosts {
+            ui.radio_value(&mut self.host, Some(host.clone()), host);
+        }
+
+        ui.label("Device");
+        ui.radio_value(&mut self.device, None, "Default");
+
+        for device in &devices.devices {
+            ui.radio_value(&mut self.device, Some(device.clone()), device);
+        }
+
+        ui.radio_value(&mut self.device, Some(NULL_DEVICE.to_string()), NULL_DEVICE);
+
+        ui.label("Sample rate");
+        ui.radio_value(&mut self.sample_rate, None, "Default");
+
+        for rate in &devices.sample_rates {
+            ui.radio_value(&mut self.sample_rate, Some(*rate), format!("{} Hz", rate));
+        }
+
+        ui.label("Input");
+        ui.radio_value(&mut self.input, InputSource::None, "None");
+        ui.radio_value(&mut self.input, InputSource::Device(None), "Default device");
+
+        for device in &devices.input_devices {
+            let source = InputSource::Device(Some(device.clone()));
+            ui.radio_value(&mut self.input, source, device);
+        }
+
+        let mut path = match &self.input {
+            InputSource::File(path) => path.clone(),
+            _ => String::new(),
+        };
+
+        ui.horizontal(|ui| {
+            ui.label("WAV file:");
+
+            if ui.text_edit_singleline(&mut path).changed() {
+                self.input = InputSource::File(path);
+            }
+        });
+
+        ui.horizontal(|ui| {
+            let mut fixed = self.buffer_size.is_some();
+            ui.checkbox(&mut fixed, "Buffer size");
+
+            let mut size = self.buffer_size.unwrap_or(512) as f64;
+
+            if fixed {
+                ui.add(DragValue::f64(&mut size).speed(16.0));
+            }
+
+            self.buffer_size = if fixed {
+                Some((size as u32).max(16).min(8192))
+            } else {
+                None
+            };
+        });
+
+        // a different host has different devices
+        if self.host != prev.host {
+            self.device = None;
+        }
+
+        if self.host != prev.host || self.device != prev.device {
+            *devices = DeviceList::scan(self);
+        }
+
+        let mut apply = false;
+
+        ui.horizontal(|ui| {
+            apply = ui.button("Apply").clicked();
+
+            if ui.button("Rescan").clicked() {
+                *devices = DeviceList::scan(self);
+            }
+        });
+
+        apply
+    }
+}
+
+/// Runs a driver in real time without a device, so the graph and scope keep
+/// working on machines without audio output.
+#[cfg(not(target_arch = "wasm32"))]
+pub struct NullDevice {
+    stop: Arc<AtomicBool>,
+    thread: Option<std::thread::JoinHandle<()>>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl NullDevice {
+    pub fn start(mut driver: Driver, sample_rate: u32, buffer_size: u32) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = stop.clone();
+
+        let thread = std::thread::spawn(move || {
+            let mut buffer = vec![0.0f32; buffer_size as usize * 2];
+            let period = Duration::from_secs_f64(buffer_size as f64 / sample_rate as f64);
+            let mut next = Instant::now();
+
+            while !thread_stop.load(Ordering::Relaxed) {
+                driver.render(&mut buffer, 2);
+
+                next += period;
+                std::thread::sleep(next.saturating_duration_since(Instant::now()));
+            }
+        });
+
+        Self {
+            stop,
+            thread: Some(thread),
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Drop for NullDevice {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
@@ -1,135 +1,649 @@
+use crate::device::*;
+use crate::input::*;
 use crate::node::*;
+use crate::scope::*;
+use crate::transport::*;
+use crate::tuning::Tuning;
 use cpal::{traits::*, *};
-use crossbeam::channel::{unbounded, Receiver, Sender};
+use crossbeam::channel::{bounded, unbounded, Receiver, Sender};
+use std::sync::Arc;
+
+#[cfg(not(target_arch = "wasm32"))]
+use crossbeam::channel::RecvTimeoutError;
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::Duration;
+
+/// Commands that can be queued before the audio thread picks them up.
+const COMMAND_QUEUE_SIZE: usize = 256;
+
+/// Parameters that can be ramping at once, changes beyond this are applied
+/// immediately.
+const MAX_RAMPS: usize = 64;
+
+/// Seconds a parameter takes to reach a new value.
+const RAMP_TIME: f64 = 0.02;
+
+/// Seconds a replaced graph takes to fade out while the new one fades in.
+const CROSSFADE_TIME: f64 = 0.03;
+
+const CONTROL_QUEUE_SIZE: usize = 16;
+
+/// How often a lost or missing device is looked for again.
+#[cfg(not(target_arch = "wasm32"))]
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(2);
 
 pub enum DriverCommand {
     SetNodes(NodeManager),
     SetFreq(f64),
+    SetProbe(Option<Probe>),
+    Transport(TransportCommand),
+    SetTuning(Arc<Tuning>),
+    /// Ramps a parameter of a node in the subpatch at `path`.
+    SetParam {
+        path: Vec<NodeId>,
+        change: ParamChange,
+    },
+}
+
+/// Values replaced on the audio thread, sent back so they are freed elsewhere.
+pub enum Garbage {
+    Nodes(NodeManager),
+    Probe(Option<Probe>),
+    Path(Vec<NodeId>),
+    Tuning(Arc<Tuning>),
+}
+
+/// Sent to the thread owning the stream.
+pub enum StreamCommand {
+    Restart(AudioSettings),
+    DeviceLost,
+}
+
+/// Sent from the driver to the UI.
+pub enum DriverEvent {
+    /// Output started on the described device, with no graph loaded.
+    Started(String),
+    Failed(String),
 }
 
 pub struct DriverHandle {
-    _stream: Option<Stream>,
+    #[cfg(target_arch = "wasm32")]
+    _streams: Option<Streams>,
+    #[cfg(target_arch = "wasm32")]
+    parts: DriverParts,
+    #[cfg(not(target_arch = "wasm32"))]
+    control: Sender<StreamCommand>,
     sender: Sender<DriverCommand>,
+    events: Receiver<DriverEvent>,
+    garbage: Receiver<Garbage>,
+    pub scope: Arc<ScopeBuffer>,
+    pub transport: Arc<TransportPosition>,
 }
 
 impl DriverHandle {
+    /// Queues `command` without blocking, it is dropped if the audio thread
+    /// is gone or has stopped taking commands.
+    fn send(&self, command: DriverCommand) {
+        if let Err(err) = self.sender.try_send(command) {
+            println!("Failed to send driver command: {}", err);
+        }
+    }
+
     pub fn set_nodes(&self, nodes: NodeManager) {
-        self.sender.send(DriverCommand::SetNodes(nodes)).unwrap();
+        self.send(DriverCommand::SetNodes(nodes));
     }
 
     pub fn set_freq(&self, freq: f64) {
-        self.sender.send(DriverCommand::SetFreq(freq)).unwrap();
+        self.send(DriverCommand::SetFreq(freq));
+    }
+
+    /// Sends the given output to the scope instead of the patch output.
+    pub fn set_probe(&self, probe: Option<Probe>) {
+        self.send(DriverCommand::SetProbe(probe));
+    }
+
+    pub fn transport(&self, command: TransportCommand) {
+        self.send(DriverCommand::Transport(command));
+    }
+
+    pub fn set_tuning(&self, tuning: Arc<Tuning>) {
+        self.send(DriverCommand::SetTuning(tuning));
+    }
+
+    /// Updates a single parameter of the running graph without resending it.
+    pub fn set_param(&self, path: Vec<NodeId>, change: ParamChange) {
+        self.send(DriverCommand::SetParam { path, change });
+    }
+
+    /// Reopens the output with `settings`.
+    pub fn restart(&mut self, settings: AudioSettings) {
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            if let Err(err) = self.control.try_send(StreamCommand::Restart(settings)) {
+                println!("Failed to restart the stream: {}", err);
+            }
+        }
+
+        #[cfg(target_arch = "wasm32")]
+        {
+            self._streams = None;
+
+            match self.parts.open(&settings) {
+                Ok(streams) => self._streams = Some(streams),
+                Err(err) => self.parts.event(DriverEvent::Failed(err.to_string())),
+            }
+        }
+    }
+
+    /// Events sent by the driver since the last call.
+    pub fn events(&self) -> Vec<DriverEvent> {
+        self.events.try_iter().collect()
+    }
+
+    /// Frees the values the audio thread is done with, should be called regularly.
+    pub fn collect_garbage(&self) {
+        for garbage in self.garbage.try_iter() {
+            drop(garbage);
+        }
     }
 }
 
+/// A parameter moving towards its target a step per sample.
+struct Ramp {
+    path: Vec<NodeId>,
+    id: NodeId,
+    index: usize,
+    value: f64,
+    target: f64,
+    step: f64,
+}
+
 pub struct Driver {
     nodes: Option<NodeManager>,
+    /// The replaced graph, faded out over `CROSSFADE_TIME`.
+    fading: Option<NodeManager>,
+    /// Progress of the crossfade from 0 to 1.
+    fade: f64,
+    /// The newest graph received during a crossfade, swapped in when it ends.
+    pending: Option<NodeManager>,
+    ramps: Vec<Ramp>,
+    sample_rate: f64,
+    /// Seconds since the stream started.
+    time: f64,
+    input: AudioInput,
+    transport: Transport,
+    position: Arc<TransportPosition>,
+    tuning: Arc<Tuning>,
     freq: f64,
+    probe: Option<Probe>,
+    scope: Arc<ScopeBuffer>,
     receiver: Receiver<DriverCommand>,
+    garbage: Sender<Garbage>,
 }
 
 impl Driver {
+    /// Applies the queued commands, called once per buffer on the audio thread.
     pub fn handle_commands(&mut self) {
-        for command in self.receiver.try_iter() {
+        while let Ok(command) = self.receiver.try_recv() {
             match command {
-                DriverCommand::SetNodes(nodes) => self.nodes = Some(nodes),
+                DriverCommand::SetNodes(nodes) => self.set_nodes(nodes),
                 DriverCommand::SetFreq(freq) => self.freq = freq,
+                DriverCommand::SetProbe(probe) => {
+                    if let Some(nodes) = self.nodes.as_mut() {
+                        nodes.set_probe(probe.as_ref());
+                    }
+
+                    let old = std::mem::replace(&mut self.probe, probe);
+                    self.discard(Garbage::Probe(old));
+                }
+                DriverCommand::Transport(command) => self.transport.apply(command),
+                DriverCommand::SetTuning(tuning) => {
+                    let old = std::mem::replace(&mut self.tuning, tuning);
+                    self.discard(Garbage::Tuning(old));
+                }
+                DriverCommand::SetParam { path, change } => self.set_param(path, change),
             }
         }
     }
 
-    pub fn run() -> Result<DriverHandle, anyhow::Error> {
-        let (sender, receiver) = unbounded();
+    /// Swaps in a new graph, crossfading from the one playing. During a
+    /// crossfade the graph waits for it to end, so quick changes don't cut
+    /// off the graph fading out. Only the newest waiting graph is kept.
+    fn set_nodes(&mut self, mut nodes: NodeManager) {
+        if self.fading.is_some() {
+            if let Some(skipped) = self.pending.replace(nodes) {
+                self.discard(Garbage::Nodes(skipped));
+            }
 
-        let f = move || -> Result<Stream, anyhow::Error> {
-            let host = default_host();
+            return;
+        }
 
-            let device = host
-                .default_output_device()
-                .expect("failed to get default device");
+        nodes.set_probe(self.probe.as_ref());
 
-            let config = device
-                .default_output_config()
-                .expect("failed to get default output config");
+        if let Some(old) = self.nodes.replace(nodes) {
+            if let Some(fading) = self.fading.replace(old) {
+                self.discard(Garbage::Nodes(fading));
+            }
 
-            let driver = Driver {
-                nodes: None,
-                freq: 440.0,
-                receiver,
-            };
+            self.fade = 0.0;
+        }
+
+        // the new graph already has the final values of the parameters
+        for ramp in self.ramps.drain(..) {
+            let _ = self.garbage.try_send(Garbage::Path(ramp.path));
+        }
+    }
+
+    fn set_param(&mut self, path: Vec<NodeId>, change: ParamChange) {
+        // a waiting graph was copied before the change, so it takes the
+        // final value right away
+        let pending = self
+            .pending
+            .as_mut()
+            .and_then(|nodes| nodes.subpatch_at(&path))
+            .and_then(|nodes| nodes.nodes.get_mut(&change.id));
+
+        if let Some(node) = pending {
+            node.inner.set_param(change.index, change.to);
+        }
+
+        let steps = (RAMP_TIME * self.sample_rate).max(1.0);
+
+        let ramp = self
+            .ramps
+            .iter_mut()
+            .find(|ramp| ramp.id == change.id && ramp.index == change.index && ramp.path == path);
+
+        if let Some(ramp) = ramp {
+            // a parameter that is already moving continues from where it is
+            ramp.target = change.to;
+            ramp.step = (change.to - ramp.value) / steps;
+
+            self.discard(Garbage::Path(path));
+        } else if self.ramps.len() < MAX_RAMPS {
+            self.ramps.push(Ramp {
+                path,
+                id: change.id,
+                index: change.index,
+                value: change.from,
+                target: change.to,
+                step: (change.to - change.from) / steps,
+            });
+        } else {
+            let node = self
+                .nodes
+                .as_mut()
+                .and_then(|nodes| nodes.subpatch_at(&path))
+                .and_then(|nodes| nodes.nodes.get_mut(&change.id));
+
+            if let Some(node) = node {
+                node.inner.set_param(change.index, change.to);
+            }
+
+            self.discard(Garbage::Path(path));
+        }
+    }
+
+    /// Moves every ramping parameter a step closer to its target.
+    fn update_ramps(&mut self) {
+        if let Some(nodes) = self.nodes.as_mut() {
+            for ramp in &mut self.ramps {
+                if (ramp.target - ramp.value).abs() <= ramp.step.abs() {
+                    ramp.value = ramp.target;
+                } else {
+                    ramp.value += ramp.step;
+                }
+
+                let node = nodes
+                    .subpatch_at(&ramp.path)
+                    .and_then(|nodes| nodes.nodes.get_mut(&ramp.id));
+
+                if let Some(node) = node {
+                    node.inner.set_param(ramp.index, ramp.value);
+                }
+            }
+        }
+
+        let mut i = 0;
+
+        while i < self.ramps.len() {
+            if self.ramps[i].value == self.ramps[i].target {
+                let ramp = self.ramps.swap_remove(i);
+                self.discard(Garbage::Path(ramp.path));
+            } else {
+                i += 1;
+            }
+        }
+    }
+
+    /// Runs the graph for one frame, without a graph the output is silent.
+    fn process(&mut self, ctx: &NodeCtx) -> (f64, f64) {
+        self.update_ramps();
+
+        let (mut left, mut right) = match self.nodes.as_mut() {
+            Some(nodes) => {
+                let (left, right) = nodes.run(ctx);
 
-            let stream = match config.sample_format() {
-                cpal::SampleFormat::F32 => run::<f32>(driver, &device, &config.into())?,
-                cpal::SampleFormat::I16 => run::<i16>(driver, &device, &config.into())?,
-                cpal::SampleFormat::U16 => run::<u16>(driver, &device, &config.into())?,
+                let probed = match &self.probe {
+                    Some(probe) => nodes.probed(probe),
+                    None => Some((left + right) * 0.5),
+                };
+
+                self.scope.push(probed.unwrap_or(0.0) as f32);
+                self.scope.set_signal(probed.is_some());
+
+                (left, right)
+            }
+            None => (0.0, 0.0),
+        };
+
+        if let Some(fading) = self.fading.as_mut() {
+            let (old_left, old_right) = fading.run(ctx);
+
+            left = old_left + (left - old_left) * self.fade;
+            right = old_right + (right - old_right) * self.fade;
+
+            self.fade += 1.0 / (CROSSFADE_TIME * self.sample_rate);
+
+            if self.fade >= 1.0 {
+                if let Some(old) = self.fading.take() {
+                    self.discard(Garbage::Nodes(old));
+                }
+
+                if let Some(nodes) = self.pending.take() {
+                    self.set_nodes(nodes);
+                }
+            }
+        }
+
+        (left, right)
+    }
+
+    /// Hands `garbage` to the UI thread to be freed. If its queue is full the
+    /// value is dropped here, which only happens if the UI stops collecting.
+    fn discard(&self, garbage: Garbage) {
+        let _ = self.garbage.try_send(garbage);
+    }
+
+    /// Renders interleaved frames of `channels` into `data`.
+    pub fn render<T: Sample>(&mut self, data: &mut [T], channels: usize) {
+        self.handle_commands();
+
+        let sample_length = 1.0 / self.sample_rate;
+        // only replaced by commands, so this is never the last reference
+        let tuning = self.tuning.clone();
+
+        for frame in data.chunks_mut(channels) {
+            let ctx = NodeCtx {
+                freq: self.freq,
+                time: self.time,
+                sample_length,
+                last_sample: 0.0,
+                audio_in: self.input.next(),
+                transport: self.transport,
+                tuning: &tuning,
             };
 
-            Ok(stream)
+            self.time += sample_length;
+            self.transport.advance(sample_length);
+
+            let (left, right) = self.process(&ctx);
+
+            let left = left.max(-5.0).min(5.0) * 0.01;
+            let right = right.max(-5.0).min(5.0) * 0.01;
+
+            // even channels get the left signal, odd channels the right
+            for (channel, sample) in frame.iter_mut().enumerate() {
+                let out = if channel % 2 == 0 { left } else { right };
+
+                *sample = Sample::from::<f32>(&(out as f32));
+            }
+        }
+
+        self.position.set(self.transport.beats);
+    }
+
+    /// Starts audio output with `settings`. On native targets the stream is
+    /// owned by a thread which reopens it when the settings change or the
+    /// device is lost, falling back to a null device if it can't be opened.
+    pub fn run(settings: AudioSettings) -> Result<DriverHandle, anyhow::Error> {
+        let (sender, receiver) = bounded(COMMAND_QUEUE_SIZE);
+        let (garbage_sender, garbage) = bounded(COMMAND_QUEUE_SIZE);
+        let (control, control_receiver) = bounded(CONTROL_QUEUE_SIZE);
+        let (event_sender, events) = unbounded();
+        let scope = Arc::new(ScopeBuffer::new());
+        let transport = Arc::new(TransportPosition::new());
+
+        let parts = DriverParts {
+            receiver,
+            garbage: garbage_sender,
+            scope: scope.clone(),
+            transport: transport.clone(),
+            events: event_sender,
+            control: control.clone(),
         };
 
         #[cfg(not(target_arch = "wasm32"))]
         {
-            std::thread::spawn(move || {
-                let _stream = f().unwrap();
-
-                std::thread::park();
-            });
+            std::thread::spawn(move || stream_thread(settings, parts, control_receiver));
 
             Ok(DriverHandle {
+                control,
                 sender,
-                _stream: None,
+                events,
+                garbage,
+                scope,
+                transport,
             })
         }
 
         #[cfg(target_arch = "wasm32")]
         {
-            let stream = f()?;
+            drop((control, control_receiver));
+
+            let streams = parts.open(&settings)?;
+
             Ok(DriverHandle {
+                _streams: Some(streams),
+                parts,
                 sender,
-                _stream: Some(stream),
+                events,
+                garbage,
+                scope,
+                transport,
             })
         }
     }
 }
 
-fn run<T: Sample>(
-    mut driver: Driver,
-    device: &Device,
-    config: &StreamConfig,
-) -> Result<Stream, anyhow::Error> {
-    let sample_rate = config.sample_rate.0 as f64;
-    let sample_length = 1.0 / sample_rate;
-    let channels = config.channels as usize;
-    let mut time = 0.0;
-
-    let stream = device.build_output_stream(
-        config,
-        move |data: &mut [T], _: &OutputCallbackInfo| {
-            for frame in data.chunks_mut(channels) {
-                let ctx = NodeCtx {
-                    freq: driver.freq,
-                    time,
-                    sample_length,
-                    last_sample: 0.0,
-                };
+/// Everything needed to create a `Driver`, kept so the stream can be reopened.
+#[derive(Clone)]
+struct DriverParts {
+    receiver: Receiver<DriverCommand>,
+    garbage: Sender<Garbage>,
+    scope: Arc<ScopeBuffer>,
+    transport: Arc<TransportPosition>,
+    events: Sender<DriverEvent>,
+    control: Sender<StreamCommand>,
+}
+
+impl DriverParts {
+    /// A driver without a graph, the UI sends it one when `Started` is received.
+    fn driver(&self, sample_rate: u32, input: AudioInput) -> Driver {
+        self.scope.set_sample_rate(sample_rate);
+
+        Driver {
+            nodes: None,
+            fading: None,
+            fade: 1.0,
+            pending: None,
+            ramps: Vec::with_capacity(MAX_RAMPS),
+            sample_rate: sample_rate as f64,
+            time: 0.0,
+            input,
+            transport: Transport::default(),
+            position: self.transport.clone(),
+            tuning: Arc::default(),
+            freq: 440.0,
+            probe: None,
+            scope: self.scope.clone(),
+            receiver: self.receiver.clone(),
+            garbage: self.garbage.clone(),
+        }
+    }
+
+    fn event(&self, event: DriverEvent) {
+        let _ = self.events.send(event);
+    }
+
+    /// Opens the output device chosen in `settings`, along with the input.
+    fn open(&self, settings: &AudioSettings) -> Result<Streams, anyhow::Error> {
+        let device = find_output_device(settings)?;
+        let (format, config) = stream_config(&device, settings)?;
 
-                driver.handle_commands();
+        let (input, input_stream, input_status) = open_input(settings, config.sample_rate.0);
 
-                time += 1.0 / sample_rate;
+        let description = format!(
+            "{}, {} Hz, {} channels{}",
+            device.name()?,
+            config.sample_rate.0,
+            config.channels,
+            input_status
+        );
+
+        let stream = match format {
+            SampleFormat::F32 => self.run::<f32>(&device, &config, input)?,
+            SampleFormat::I16 => self.run::<i16>(&device, &config, input)?,
+            SampleFormat::U16 => self.run::<u16>(&device, &config, input)?,
+        };
+
+        self.event(DriverEvent::Started(description));
+
+        Ok(Streams {
+            _output: stream,
+            _input: input_stream,
+        })
+    }
 
-                let wave = driver.nodes.as_mut().unwrap().run(&ctx).max(-5.0).min(5.0);
+    fn run<T: Sample>(
+        &self,
+        device: &Device,
+        config: &StreamConfig,
+        input: AudioInput,
+    ) -> Result<Stream, anyhow::Error> {
+        let mut driver = self.driver(config.sample_rate.0, input);
+        let channels = config.channels as usize;
+        let control = self.control.clone();
 
-                let out = wave * 0.01;
+        let stream = device.build_output_stream(
+            config,
+            move |data: &mut [T], _: &OutputCallbackInfo| driver.render(data, channels),
+            move |err| {
+                println!("Error: {}", err);
 
-                for sample in frame {
-                    *sample = Sample::from::<f32>(&(out as f32));
+                if let StreamError::DeviceNotAvailable = err {
+                    let _ = control.try_send(StreamCommand::DeviceLost);
                 }
+            },
+        )?;
+
+        stream.play()?;
+
+        Ok(stream)
+    }
+}
+
+/// An output stream with the input stream feeding it, if any.
+struct Streams {
+    _output: Stream,
+    _input: Option<Stream>,
+}
+
+/// The output owned by the stream thread.
+#[cfg(not(target_arch = "wasm32"))]
+enum Output {
+    Device {
+        _streams: Streams,
+    },
+    /// `fallback` is set if the chosen device couldn't be opened.
+    Null {
+        _device: NullDevice,
+        _input: Option<Stream>,
+        fallback: bool,
+    },
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Output {
+    fn start(settings: &AudioSettings, parts: &DriverParts) -> Self {
+        let mut status = String::new();
+
+        if settings.device.as_deref() != Some(NULL_DEVICE) {
+            match parts.open(settings) {
+                Ok(streams) => return Output::Device { _streams: streams },
+                Err(err) => status = format!(" ({})", err),
             }
-        },
-        |err| println!("Error: {}", err),
-    )?;
+        }
+
+        let sample_rate = settings.sample_rate.unwrap_or(44100);
+        let buffer_size = settings.buffer_size.unwrap_or(512);
+
+        let (input, input_stream, input_status) = open_input(settings, sample_rate);
+
+        parts.event(DriverEvent::Started(format!(
+            "{}, {} Hz{}{}",
+            NULL_DEVICE, sample_rate, input_status, status
+        )));
+
+        let driver = parts.driver(sample_rate, input);
+
+        Output::Null {
+            _device: NullDevice::start(driver, sample_rate, buffer_size),
+            _input: input_stream,
+            fallback: settings.device.as_deref() != Some(NULL_DEVICE),
+        }
+    }
+
+    fn is_fallback(&self) -> bool {
+        matches!(self, Output::Null { fallback: true, .. })
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn stream_thread(
+    mut settings: AudioSettings,
+    parts: DriverParts,
+    control: Receiver<StreamCommand>,
+) {
+    let mut output = Output::start(&settings, &parts);
+
+    loop {
+        // while on the fallback the device is retried every so often
+        let command = if output.is_fallback() {
+            match control.recv_timeout(RECONNECT_INTERVAL) {
+                Ok(command) => Some(command),
+                Err(RecvTimeoutError::Timeout) => None,
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+        } else {
+            match control.recv() {
+                Ok(command) => Some(command),
+                Err(_) => break,
+            }
+        };
 
-    stream.play()?;
+        match command {
+            Some(StreamCommand::Restart(new_settings)) => settings = new_settings,
+            Some(StreamCommand::DeviceLost) => std::thread::sleep(RECONNECT_INTERVAL),
+            None => {
+                if find_output_device(&settings).is_err() {
+                    continue;
+                }
+            }
+        }
 
-    Ok(stream)
+        // the old stream has to be closed before the device is opened again
+        drop(output);
+        output = Output::start(&settings, &parts);
+    }
 }
@@ -0,0 +1,222 @@
+use std::f64::consts::PI;
+
+/// Oversampling factors offered by nodes, the last is the largest supported.
+pub const OVERSAMPLING: [usize; 4] = [1, 2, 4, 8];
+pub const MAX_OVERSAMPLING: usize = OVERSAMPLING[OVERSAMPLING.len() - 1];
+
+/// Taps of the resampling filters per unit of the oversampling factor, on
+/// each side of the centre.
+const TAPS_PER_FACTOR: usize = 8;
+
+/// The longest latency of an `Oversampler`, in samples at the original rate.
+pub const MAX_LATENCY: usize = 2 * TAPS_PER_FACTOR;
+
+/// Level in dB given for silence.
+pub const MIN_DB: f64 = -120.0;
+
+pub fn to_db(level: f64) -> f64 {
+    (20.0 * level.log10()).max(MIN_DB)
+}
+
+pub fn from_db(db: f64) -> f64 {
+    10.0f64.powf(db / 20.0)
+}
+
+/// Windowed sinc low pass with its cutoff at `cutoff` times the sample rate.
+fn lowpass(len: usize, cutoff: f64) -> Vec<f64> {
+    let centre = (len - 1) as f64 * 0.5;
+
+    let mut taps: Vec<f64> = (0..len)
+        .map(|i| {
+            let x = i as f64 - centre;
+            let sinc = if x == 0.0 {
+                2.0 * cutoff
+            } else {
+                (2.0 * PI * cutoff * x).sin() / (PI * x)
+            };
+
+            // Blackman window
+            let t = i as f64 / (len - 1) as f64;
+            let window = 0.42 - 0.5 * (2.0 * PI * t).cos() + 0.08 * (4.0 * PI * t).cos();
+
+            sinc * window
+        })
+        .collect();
+
+    let sum: f64 = taps.iter().sum();
+
+    for tap in &mut taps {
+        *tap /= sum;
+    }
+
+    taps
+}
+
+/// Converts a signal to `factor` times the sample rate and back, filtering
+/// out what would alias in each direction.
+#[derive(Clone)]
+pub struct Oversampler {
+    factor: usize,
+    taps: Vec<f64>,
+    /// Input samples for upsampling, newest at `up_pos`.
+    up: Vec<f64>,
+    up_pos: usize,
+    /// Oversampled samples for downsampling, newest at `down_pos`.
+    down: Vec<f64>,
+    down_pos: usize,
+}
+
+impl Oversampler {
+    pub fn new(factor: usize) -> Self {
+        let factor = factor.max(1);
+        let len = TAPS_PER_FACTOR * 2 * factor + 1;
+
+        // just under the original Nyquist frequency
+        let taps = lowpass(len, 0.45 / factor as f64);
+
+        Self {
+            factor,
+            taps,
+            up: vec![0.0; (len + factor - 1) / factor],
+            up_pos: 0,
+            down: vec![0.0; len],
+            down_pos: 0,
+        }
+    }
+
+    pub fn factor(&self) -> usize {
+        self.factor
+    }
+
+    /// Samples at the original rate a signal is delayed by going up and back
+    /// down, which signals mixed with it must be delayed by too.
+    pub fn latency(&self) -> f64 {
+        Self::upsample_latency(self.factor) + Self::downsample_latency(self.factor)
+    }
+
+    /// Samples at the original rate a signal is delayed by upsampling, half
+    /// the length of the filter.
+    pub fn upsample_latency(factor: usize) -> f64 {
+        if factor <= 1 {
+            0.0
+        } else {
+            TAPS_PER_FACTOR as f64
+        }
+    }
+
+    /// Samples at the original rate a signal is delayed by downsampling,
+    /// which keeps the last of the `factor` samples.
+    pub fn downsample_latency(factor: usize) -> f64 {
+        if factor <= 1 {
+            0.0
+        } else {
+            TAPS_PER_FACTOR as f64 - (factor - 1) as f64 / factor as f64
+        }
+    }
+
+    /// Fills `out` with `factor` samples interpolating `input`.
+    pub fn upsample(&mut self, input: f64, out: &mut [f64]) {
+        if self.factor == 1 {
+            out[0] = input;
+            return;
+        }
+
+        let len = self.up.len();
+        self.up_pos = (self.up_pos + 1) % len;
+        self.up[self.up_pos] = input;
+
+        // only every `factor`th sample of the zero stuffed signal is non
+        // zero, so each output phase uses every `factor`th tap
+        for (phase, out) in out.iter_mut().enumerate().take(self.factor) {
+            let mut sum = 0.0;
+
+            for (k, tap) in self.taps[phase..].iter().step_by(self.factor).enumerate() {
+                sum += tap * self.up[(self.up_pos + len - k) % len];
+            }
+
+            *out = sum * self.factor as f64;
+        }
+    }
+
+    /// Filters the `factor` samples of `input` down to one.
+    pub fn downsample(&mut self, input: &[f64]) -> f64 {
+        if self.factor == 1 {
+            return input[0];
+        }
+
+        let len = self.down.len();
+
+        for sample in input.iter().take(self.factor) {
+            self.down_pos = (self.down_pos + 1) % len;
+            self.down[self.down_pos] = *sample;
+        }
+
+        self.taps
+            .iter()
+            .enumerate()
+            .map(|(k, tap)| tap * self.down[(self.down_pos + len - k) % len])
+            .sum()
+    }
+}
+
+/// A circular buffer of past samples, read at fractional delays.
+#[derive(Clone)]
+pub struct DelayLine {
+    buffer: Vec<f64>,
+    /// Position of the newest sample.
+    pos: usize,
+}
+
+impl DelayLine {
+    pub fn new(len: usize) -> Self {
+        Self {
+            buffer: vec![0.0; len.max(2)],
+            pos: 0,
+        }
+    }
+
+    pub fn write(&mut self, sample: f64) {
+        self.pos = (self.pos + 1) % self.buffer.len();
+        self.buffer[self.pos] = sample;
+    }
+
+    /// The signal `delay` samples before the next write, interpolated
+    /// linearly and limited to the length of the buffer.
+    pub fn read(&self, delay: f64) -> f64 {
+        let len = self.buffer.len();
+        let back = (delay - 1.0).max(0.0).min((len - 2) as f64);
+        let index = back.floor() as usize;
+        let t = back - index as f64;
+
+        let newer = self.buffer[(self.pos + len - index) % len];
+        let older = self.buffer[(self.pos + len - index - 1) % len];
+
+        newer + (older - newer) * t
+    }
+}
+
+/// A first order all-pass filter, shifting the phase by 90 degrees at the
+/// frequency its coefficient is made for.
+#[derive(Clone, Copy, Default)]
+pub struct AllPass {
+    last_input: f64,
+    last_output: f64,
+}
+
+impl AllPass {
+    /// The coefficient for a 90 degree shift at `freq`.
+    pub fn coefficient(freq: f64, sample_length: f64) -> f64 {
+        let t = (PI * freq * sample_length).min(1.5).tan();
+
+        (t - 1.0) / (t + 1.0)
+    }
+
+    pub fn process(&mut self, input: f64, coefficient: f64) -> f64 {
+        let output = coefficient * input + self.last_input - coefficient * self.last_output;
+
+        self.last_input = input;
+        self.last_output = output;
+
+        output
+    }
+}
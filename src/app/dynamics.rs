@@ -0,0 +1,345 @@
+use crate::dsp::{from_db, to_db};
+use crate::knob::labelled_knob;
+use crate::node::*;
+use eframe::egui::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// Gain reduction shown by the compressor's meter at its full width, in dB.
+const METER_RANGE: f64 = 24.0;
+
+/// Gain reduction in dB written by the node on the audio thread and shown
+/// by its copy in the UI, which shares it.
+#[derive(Default)]
+pub struct GainReduction(AtomicU64);
+
+impl GainReduction {
+    pub fn set(&self, db: f64) {
+        self.0.store(db.to_bits(), Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> f64 {
+        f64::from_bits(self.0.load(Ordering::Relaxed))
+    }
+}
+
+/// Coefficient for a one pole smoother covering 63% of a step in `time_ms`.
+fn smoothing(time_ms: f64, sample_length: f64) -> f64 {
+    if time_ms <= 0.0 {
+        0.0
+    } else {
+        (-sample_length / (time_ms * 0.001)).exp()
+    }
+}
+
+/// Peak level in dB of the sidechain, or of the input if nothing is
+/// connected to it.
+fn detect(input: SlotValue, sidechain: SlotValue) -> f64 {
+    let (left, right) = match sidechain {
+        SlotValue::None => input.unwrap_stereo(0.0),
+        sidechain => sidechain.unwrap_stereo(0.0),
+    };
+
+    to_db(left.abs().max(right.abs()))
+}
+
+/// A bar filling from the right with the gain reduction, full at `range` dB.
+fn reduction_ui(ui: &mut Ui, reduction: &GainReduction, range: f64) {
+    let db = reduction.get();
+
+    ui.horizontal(|ui| {
+        let (rect, _) = ui.allocate_exact_size(Vec2::new(120.0, 10.0), Sense::hover());
+        let width = rect.width() * (db / range).max(0.0).min(1.0) as f32;
+        let bar = Rect::from_min_max(Pos2::new(rect.right() - width, rect.top()), rect.max);
+
+        let painter = ui.painter();
+        painter.rect_filled(rect, 0.0, ui.style().visuals.extreme_bg_color);
+        painter.rect_filled(bar, 0.0, Color32::from_rgb(220, 80, 80));
+
+        ui.label(format!("-{:.1} dB", db));
+    });
+
+    // keep the meter falling back
+    if db > 0.01 {
+        ui.ctx().request_repaint();
+    }
+}
+
+/// Reduces the level of its input by `ratio` above `threshold`, with the
+/// level taken from the sidechain input when it is connected.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct CompressorNode {
+    /// Level in dB above which the gain is reduced.
+    threshold: f64,
+    /// Decibels over the threshold for each decibel over it at the output.
+    ratio: f64,
+    /// Milliseconds to react to the level rising.
+    attack: f64,
+    /// Milliseconds to recover after the level falls.
+    release: f64,
+    /// Width in dB of the range around the threshold where the ratio
+    /// changes gradually.
+    knee: f64,
+    /// Gain in dB applied after compressing.
+    makeup: f64,
+    /// Current gain reduction in dB.
+    #[serde(skip)]
+    envelope: f64,
+    #[serde(skip)]
+    reduction: Arc<GainReduction>,
+}
+
+impl CompressorNode {
+    pub fn new() -> Self {
+        Self {
+            threshold: -20.0,
+            ratio: 4.0,
+            attack: 10.0,
+            release: 100.0,
+            knee: 6.0,
+            makeup: 0.0,
+            envelope: 0.0,
+            reduction: Arc::default(),
+        }
+    }
+
+    /// Gain reduction in dB for a level in dB, before smoothing.
+    fn curve(&self, level: f64) -> f64 {
+        let over = level - self.threshold;
+        let slope = 1.0 - 1.0 / self.ratio.max(1.0);
+
+        if 2.0 * over <= -self.knee {
+            0.0
+        } else if 2.0 * over.abs() < self.knee {
+            slope * (over + self.knee * 0.5).powi(2) / (2.0 * self.knee)
+        } else {
+            slope * over
+        }
+    }
+}
+
+impl Node for CompressorNode {
+    fn name(&self) -> &str {
+        "Compressor"
+    }
+
+    fn input_slot_types(&self) -> &[(&'static str, SlotType)] {
+        &[("in", SlotType::Stereo), ("sidechain", SlotType::Stereo)]
+    }
+
+    fn output_slot_types(&self) -> &[(&'static str, SlotType)] {
+        &[("out", SlotType::Stereo)]
+    }
+
+    fn display_out(&self) -> &Option<&str> {
+        &Some("out")
+    }
+
+    /// Previews get their own meter, so they don't show on the node.
+    fn setup(&mut self) {
+        self.envelope = 0.0;
+        self.reduction = Arc::default();
+    }
+
+    fn run(
+        &mut self,
+        ctx: &NodeCtx,
+        input: HashMap<String, SlotValue>,
+    ) -> Vec<(&'static str, SlotValue)> {
+        let (left, right) = match input["in"] {
+            SlotValue::None => return vec![("out", SlotValue::None)],
+            value => value.unwrap_stereo(0.0),
+        };
+
+        let target = self.curve(detect(input["in"], input["sidechain"]));
+        let time = if target > self.envelope {
+            self.attack
+        } else {
+            self.release
+        };
+
+        let coefficient = smoothing(time, ctx.sample_length);
+        self.envelope = target + (self.envelope - target) * coefficient;
+        self.reduction.set(self.envelope);
+
+        let gain = from_db(self.makeup - self.envelope);
+
+        vec![("out", SlotValue::Stereo(left * gain, right * gain))]
+    }
+
+    fn ui(&mut self, ui: &mut Ui) -> bool {
+        ui.vertical(|ui| {
+            ui.horizontal(|ui| {
+                labelled_knob(ui, "Threshold", &mut self.threshold, -60.0, 0.0);
+                labelled_knob(ui, "Ratio", &mut self.ratio, 1.0, 20.0);
+                labelled_knob(ui, "Attack", &mut self.attack, 0.1, 100.0);
+                labelled_knob(ui, "Release", &mut self.release, 10.0, 1000.0);
+                labelled_knob(ui, "Knee", &mut self.knee, 0.0, 24.0);
+                labelled_knob(ui, "Makeup", &mut self.makeup, 0.0, 24.0);
+            });
+
+            reduction_ui(ui, &self.reduction, METER_RANGE);
+        });
+
+        false
+    }
+
+    fn params(&self) -> Vec<f64> {
+        vec![
+            self.threshold,
+            self.ratio,
+            self.attack,
+            self.release,
+            self.knee,
+            self.makeup,
+        ]
+    }
+
+    fn set_param(&mut self, index: usize, value: f64) {
+        match index {
+            0 => self.threshold = value,
+            1 => self.ratio = value,
+            2 => self.attack = value,
+            3 => self.release = value,
+            4 => self.knee = value,
+            5 => self.makeup = value,
+            _ => {}
+        }
+    }
+}
+
+/// Turns its input down by `range` while the level is below `threshold`,
+/// with the level taken from the sidechain input when it is connected.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct GateNode {
+    /// Level in dB above which the gate opens.
+    threshold: f64,
+    /// Milliseconds to open.
+    attack: f64,
+    /// Milliseconds the gate stays open after the level falls.
+    hold: f64,
+    /// Milliseconds to close.
+    release: f64,
+    /// Attenuation in dB while closed.
+    range: f64,
+    /// How far the gate is open, from 0 to 1.
+    #[serde(skip)]
+    open: f64,
+    /// Seconds left before the gate starts closing.
+    #[serde(skip)]
+    hold_left: f64,
+    #[serde(skip)]
+    reduction: Arc<GainReduction>,
+}
+
+impl GateNode {
+    pub fn new() -> Self {
+        Self {
+            threshold: -50.0,
+            attack: 1.0,
+            hold: 50.0,
+            release: 200.0,
+            range: 80.0,
+            open: 0.0,
+            hold_left: 0.0,
+            reduction: Arc::default(),
+        }
+    }
+}
+
+impl Node for GateNode {
+    fn name(&self) -> &str {
+        "Noise Gate"
+    }
+
+    fn input_slot_types(&self) -> &[(&'static str, SlotType)] {
+        &[("in", SlotType::Stereo), ("sidechain", SlotType::Stereo)]
+    }
+
+    fn output_slot_types(&self) -> &[(&'static str, SlotType)] {
+        &[("out", SlotType::Stereo)]
+    }
+
+    fn display_out(&self) -> &Option<&str> {
+        &Some("out")
+    }
+
+    /// Previews get their own meter, so they don't show on the node.
+    fn setup(&mut self) {
+        self.open = 0.0;
+        self.hold_left = 0.0;
+        self.reduction = Arc::default();
+    }
+
+    fn run(
+        &mut self,
+        ctx: &NodeCtx,
+        input: HashMap<String, SlotValue>,
+    ) -> Vec<(&'static str, SlotValue)> {
+        let (left, right) = match input["in"] {
+            SlotValue::None => return vec![("out", SlotValue::None)],
+            value => value.unwrap_stereo(0.0),
+        };
+
+        if detect(input["in"], input["sidechain"]) > self.threshold {
+            self.hold_left = self.hold * 0.001;
+        } else {
+            self.hold_left -= ctx.sample_length;
+        }
+
+        let (target, time) = if self.hold_left > 0.0 {
+            (1.0, self.attack)
+        } else {
+            (0.0, self.release)
+        };
+
+        let coefficient = smoothing(time, ctx.sample_length);
+        self.open = target + (self.open - target) * coefficient;
+
+        let reduction = self.range * (1.0 - self.open);
+        self.reduction.set(reduction);
+
+        let gain = from_db(-reduction);
+
+        vec![("out", SlotValue::Stereo(left * gain, right * gain))]
+    }
+
+    fn ui(&mut self, ui: &mut Ui) -> bool {
+        ui.vertical(|ui| {
+            ui.horizontal(|ui| {
+                labelled_knob(ui, "Threshold", &mut self.threshold, -80.0, 0.0);
+                labelled_knob(ui, "Attack", &mut self.attack, 0.1, 50.0);
+                labelled_knob(ui, "Hold", &mut self.hold, 0.0, 500.0);
+                labelled_knob(ui, "Release", &mut self.release, 10.0, 2000.0);
+                labelled_knob(ui, "Range", &mut self.range, 0.0, 80.0);
+            });
+
+            reduction_ui(ui, &self.reduction, self.range);
+        });
+
+        false
+    }
+
+    fn params(&self) -> Vec<f64> {
+        vec![
+            self.threshold,
+            self.attack,
+            self.hold,
+            self.release,
+            self.range,
+        ]
+    }
+
+    fn set_param(&mut self, index: usize, value: f64) {
+        match index {
+            0 => self.threshold = value,
+            1 => self.attack = value,
+            2 => self.hold = value,
+            3 => self.release = value,
+            4 => self.range = value,
+            _ => {}
+        }
+    }
+}
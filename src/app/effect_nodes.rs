@@ -0,0 +1,408 @@
+use crate::dsp::*;
+use crate::knob::labelled_knob;
+use crate::node::*;
+use eframe::egui::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::f64::consts::PI;
+
+/// Samples kept by each delay line, enough for the longest chorus delay at
+/// 384 kHz, the rate of an 8 times oversampled group at 48 kHz.
+const DELAY_SAMPLES: usize = 16384;
+
+/// Range the phaser sweeps its all-pass frequency over.
+const PHASER_MIN_FREQ: f64 = 100.0;
+const PHASER_MAX_FREQ: f64 = 4000.0;
+
+const MAX_STAGES: usize = 12;
+const STAGES: [usize; 5] = [2, 4, 6, 8, 12];
+
+/// A sine LFO from 0 to 1 for each channel, with the right one `spread`
+/// half periods behind the left one.
+fn stereo_lfo(phase: f64, spread: f64) -> [f64; 2] {
+    let lfo = |phase: f64| 0.5 - 0.5 * (2.0 * PI * phase).cos();
+
+    [lfo(phase), lfo(phase + spread * 0.5)]
+}
+
+/// The parameters and delay lines of the chorus and flanger, which differ
+/// only in their delay times.
+#[derive(Clone, Serialize, Deserialize)]
+struct ModulatedDelay {
+    /// LFO frequency in Hz.
+    rate: f64,
+    /// Part of the delay sweep used, from 0 to 1.
+    depth: f64,
+    /// Part of the delayed signal fed back into the delay line.
+    feedback: f64,
+    /// LFO phase difference between the channels, from 0 to 1 for 0 to 180
+    /// degrees.
+    spread: f64,
+    /// Part of the output that is delayed.
+    mix: f64,
+    #[serde(skip)]
+    lines: Option<[DelayLine; 2]>,
+    /// Position in the LFO period from 0 to 1.
+    #[serde(skip)]
+    phase: f64,
+}
+
+impl ModulatedDelay {
+    fn new(rate: f64, feedback: f64) -> Self {
+        Self {
+            rate,
+            depth: 0.5,
+            feedback,
+            spread: 0.5,
+            mix: 0.5,
+            lines: Some(Self::create_lines()),
+            phase: 0.0,
+        }
+    }
+
+    fn create_lines() -> [DelayLine; 2] {
+        [DelayLine::new(DELAY_SAMPLES), DelayLine::new(DELAY_SAMPLES)]
+    }
+
+    fn reset(&mut self) {
+        self.lines = Some(Self::create_lines());
+        self.phase = 0.0;
+    }
+
+    /// Delays each channel by `base` plus up to `sweep` seconds.
+    fn process(&mut self, ctx: &NodeCtx, input: [f64; 2], base: f64, sweep: f64) -> [f64; 2] {
+        // created on the UI thread, unless the node was loaded without them
+        let mut lines = self.lines.take().unwrap_or_else(Self::create_lines);
+
+        self.phase = (self.phase + self.rate * ctx.sample_length) % 1.0;
+
+        let lfo = stereo_lfo(self.phase, self.spread);
+        let mut output = [0.0; 2];
+
+        for (channel, line) in lines.iter_mut().enumerate() {
+            let delay = (base + sweep * self.depth * lfo[channel]) / ctx.sample_length;
+            let delayed = line.read(delay);
+
+            line.write(input[channel] + delayed * self.feedback);
+            output[channel] = input[channel] + (delayed - input[channel]) * self.mix;
+        }
+
+        self.lines = Some(lines);
+
+        output
+    }
+
+    fn ui(&mut self, ui: &mut Ui, max_rate: f64, max_feedback: f64) {
+        ui.horizontal(|ui| {
+            labelled_knob(ui, "Rate", &mut self.rate, 0.01, max_rate);
+            labelled_knob(ui, "Depth", &mut self.depth, 0.0, 1.0);
+            labelled_knob(
+                ui,
+                "Feedback",
+                &mut self.feedback,
+                -max_feedback,
+                max_feedback,
+            );
+            labelled_knob(ui, "Spread", &mut self.spread, 0.0, 1.0);
+            labelled_knob(ui, "Mix", &mut self.mix, 0.0, 1.0);
+        });
+    }
+
+    fn params(&self) -> Vec<f64> {
+        vec![self.rate, self.depth, self.feedback, self.spread, self.mix]
+    }
+
+    fn set_param(&mut self, index: usize, value: f64) {
+        match index {
+            0 => self.rate = value,
+            1 => self.depth = value,
+            2 => self.feedback = value,
+            3 => self.spread = value,
+            4 => self.mix = value,
+            _ => {}
+        }
+    }
+}
+
+/// Thickens its input by mixing in copies delayed by 10 to 30 ms, with the
+/// delays swept by an LFO.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ChorusNode {
+    delay: ModulatedDelay,
+}
+
+impl ChorusNode {
+    pub fn new() -> Self {
+        Self {
+            delay: ModulatedDelay::new(0.8, 0.0),
+        }
+    }
+}
+
+impl Node for ChorusNode {
+    fn name(&self) -> &str {
+        "Chorus"
+    }
+
+    fn input_slot_types(&self) -> &[(&'static str, SlotType)] {
+        &[("in", SlotType::Stereo)]
+    }
+
+    fn output_slot_types(&self) -> &[(&'static str, SlotType)] {
+        &[("out", SlotType::Stereo)]
+    }
+
+    fn display_out(&self) -> &Option<&str> {
+        &Some("out")
+    }
+
+    fn setup(&mut self) {
+        self.delay.reset();
+    }
+
+    fn run(
+        &mut self,
+        ctx: &NodeCtx,
+        input: HashMap<String, SlotValue>,
+    ) -> Vec<(&'static str, SlotValue)> {
+        let (left, right) = match input["in"] {
+            SlotValue::None => return vec![("out", SlotValue::None)],
+            value => value.unwrap_stereo(0.0),
+        };
+
+        let [left, right] = self.delay.process(ctx, [left, right], 0.01, 0.02);
+
+        vec![("out", SlotValue::Stereo(left, right))]
+    }
+
+    fn ui(&mut self, ui: &mut Ui) -> bool {
+        self.delay.ui(ui, 5.0, 0.5);
+
+        false
+    }
+
+    fn params(&self) -> Vec<f64> {
+        self.delay.params()
+    }
+
+    fn set_param(&mut self, index: usize, value: f64) {
+        self.delay.set_param(index, value);
+    }
+
+    /// Creates the delay lines.
+    fn load_resources(&mut self) {
+        self.delay.reset();
+    }
+}
+
+/// Sweeps comb filter notches through its input by mixing in a copy delayed
+/// by up to 10 ms, with feedback for a more resonant sound.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct FlangerNode {
+    delay: ModulatedDelay,
+}
+
+impl FlangerNode {
+    pub fn new() -> Self {
+        Self {
+            delay: ModulatedDelay::new(0.2, 0.5),
+        }
+    }
+}
+
+impl Node for FlangerNode {
+    fn name(&self) -> &str {
+        "Flanger"
+    }
+
+    fn input_slot_types(&self) -> &[(&'static str, SlotType)] {
+        &[("in", SlotType::Stereo)]
+    }
+
+    fn output_slot_types(&self) -> &[(&'static str, SlotType)] {
+        &[("out", SlotType::Stereo)]
+    }
+
+    fn display_out(&self) -> &Option<&str> {
+        &Some("out")
+    }
+
+    fn setup(&mut self) {
+        self.delay.reset();
+    }
+
+    fn run(
+        &mut self,
+        ctx: &NodeCtx,
+        input: HashMap<String, SlotValue>,
+    ) -> Vec<(&'static str, SlotValue)> {
+        let (left, right) = match input["in"] {
+            SlotValue::None => return vec![("out", SlotValue::None)],
+            value => value.unwrap_stereo(0.0),
+        };
+
+        let [left, right] = self.delay.process(ctx, [left, right], 0.0005, 0.0095);
+
+        vec![("out", SlotValue::Stereo(left, right))]
+    }
+
+    fn ui(&mut self, ui: &mut Ui) -> bool {
+        self.delay.ui(ui, 5.0, 0.95);
+
+        false
+    }
+
+    fn params(&self) -> Vec<f64> {
+        self.delay.params()
+    }
+
+    fn set_param(&mut self, index: usize, value: f64) {
+        self.delay.set_param(index, value);
+    }
+
+    /// Creates the delay lines.
+    fn load_resources(&mut self) {
+        self.delay.reset();
+    }
+}
+
+/// Sweeps notches through its input by mixing it with a copy passed through
+/// a chain of all-pass filters, whose frequency is swept by an LFO.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct PhaserNode {
+    /// LFO frequency in Hz.
+    rate: f64,
+    /// Part of the frequency range swept, from 0 to 1.
+    depth: f64,
+    /// Part of the filtered signal fed back into the chain.
+    feedback: f64,
+    /// LFO phase difference between the channels, from 0 to 1 for 0 to 180
+    /// degrees.
+    spread: f64,
+    /// Amount of filtered signal, with 1 giving the deepest notches.
+    mix: f64,
+    /// All-pass filters per channel, with a notch for every two.
+    stages: usize,
+    #[serde(skip)]
+    filters: [[AllPass; MAX_STAGES]; 2],
+    #[serde(skip)]
+    last: [f64; 2],
+    /// Position in the LFO period from 0 to 1.
+    #[serde(skip)]
+    phase: f64,
+}
+
+impl PhaserNode {
+    pub fn new() -> Self {
+        Self {
+            rate: 0.3,
+            depth: 0.8,
+            feedback: 0.3,
+            spread: 0.5,
+            mix: 1.0,
+            stages: 4,
+            filters: Default::default(),
+            last: [0.0; 2],
+            phase: 0.0,
+        }
+    }
+}
+
+impl Node for PhaserNode {
+    fn name(&self) -> &str {
+        "Phaser"
+    }
+
+    fn input_slot_types(&self) -> &[(&'static str, SlotType)] {
+        &[("in", SlotType::Stereo)]
+    }
+
+    fn output_slot_types(&self) -> &[(&'static str, SlotType)] {
+        &[("out", SlotType::Stereo)]
+    }
+
+    fn display_out(&self) -> &Option<&str> {
+        &Some("out")
+    }
+
+    fn setup(&mut self) {
+        self.filters = Default::default();
+        self.last = [0.0; 2];
+        self.phase = 0.0;
+    }
+
+    fn run(
+        &mut self,
+        ctx: &NodeCtx,
+        input: HashMap<String, SlotValue>,
+    ) -> Vec<(&'static str, SlotValue)> {
+        let (left, right) = match input["in"] {
+            SlotValue::None => return vec![("out", SlotValue::None)],
+            value => value.unwrap_stereo(0.0),
+        };
+
+        self.phase = (self.phase + self.rate * ctx.sample_length) % 1.0;
+
+        let lfo = stereo_lfo(self.phase, self.spread);
+        let stages = self.stages.min(MAX_STAGES);
+        let input = [left, right];
+        let mut output = [0.0; 2];
+
+        for (channel, (filters, last)) in self.filters.iter_mut().zip(&mut self.last).enumerate() {
+            // swept exponentially, so it moves evenly through the octaves
+            let freq = PHASER_MIN_FREQ
+                * (PHASER_MAX_FREQ / PHASER_MIN_FREQ).powf(self.depth * lfo[channel]);
+            let coefficient = AllPass::coefficient(freq, ctx.sample_length);
+
+            let mut wet = input[channel] + *last * self.feedback;
+
+            for filter in &mut filters[..stages] {
+                wet = filter.process(wet, coefficient);
+            }
+
+            *last = wet;
+            output[channel] = input[channel] + (wet - input[channel]) * self.mix * 0.5;
+        }
+
+        vec![("out", SlotValue::Stereo(output[0], output[1]))]
+    }
+
+    fn ui(&mut self, ui: &mut Ui) -> bool {
+        let prev_stages = self.stages;
+
+        ui.vertical(|ui| {
+            ui.horizontal(|ui| {
+                labelled_knob(ui, "Rate", &mut self.rate, 0.01, 5.0);
+                labelled_knob(ui, "Depth", &mut self.depth, 0.0, 1.0);
+                labelled_knob(ui, "Feedback", &mut self.feedback, -0.9, 0.9);
+                labelled_knob(ui, "Spread", &mut self.spread, 0.0, 1.0);
+                labelled_knob(ui, "Mix", &mut self.mix, 0.0, 1.0);
+            });
+
+            ui.horizontal(|ui| {
+                ui.label("Stages");
+
+                for stages in &STAGES {
+                    ui.radio_value(&mut self.stages, *stages, stages.to_string());
+                }
+            });
+        });
+
+        self.stages != prev_stages
+    }
+
+    fn params(&self) -> Vec<f64> {
+        vec![self.rate, self.depth, self.feedback, self.spread, self.mix]
+    }
+
+    fn set_param(&mut self, index: usize, value: f64) {
+        match index {
+            0 => self.rate = value,
+            1 => self.depth = value,
+            2 => self.feedback = value,
+            3 => self.spread = value,
+            4 => self.mix = value,
+            _ => {}
+        }
+    }
+}
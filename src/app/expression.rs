@@ -0,0 +1,629 @@
+use crate::node::*;
+use eframe::egui::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Names the formula can read, in the order their values are passed to
+/// `Program::eval`.
+const VARIABLES: [&str; 7] = ["a", "b", "c", "d", "t", "freq", "sr"];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Compare {
+    Less,
+    Greater,
+    LessEqual,
+    GreaterEqual,
+    Equal,
+    NotEqual,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Number(f64),
+    Ident(String),
+    Op(char),
+    Compare(Compare),
+    LeftParen,
+    RightParen,
+    Comma,
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let ch = chars[i];
+
+        if ch.is_whitespace() {
+            i += 1;
+        } else if ch.is_ascii_digit() || ch == '.' {
+            let start = i;
+
+            while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                i += 1;
+            }
+
+            let text: String = chars[start..i].iter().collect();
+            let number = text
+                .parse()
+                .map_err(|_| format!("Invalid number {}", text))?;
+
+            tokens.push(Token::Number(number));
+        } else if ch.is_ascii_alphabetic() || ch == '_' {
+            let start = i;
+
+            while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+
+            tokens.push(Token::Ident(chars[start..i].iter().collect()));
+        } else if chars.get(i + 1) == Some(&'=') && "<>=!".contains(ch) {
+            tokens.push(Token::Compare(match ch {
+                '<' => Compare::LessEqual,
+                '>' => Compare::GreaterEqual,
+                '=' => Compare::Equal,
+                _ => Compare::NotEqual,
+            }));
+            i += 2;
+        } else {
+            tokens.push(match ch {
+                '+' | '-' | '*' | '/' | '%' | '^' => Token::Op(ch),
+                '<' => Token::Compare(Compare::Less),
+                '>' => Token::Compare(Compare::Greater),
+                '(' => Token::LeftParen,
+                ')' => Token::RightParen,
+                ',' => Token::Comma,
+                _ => return Err(format!("Unexpected '{}'", ch)),
+            });
+            i += 1;
+        }
+    }
+
+    Ok(tokens)
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Function {
+    Sin,
+    Cos,
+    Tan,
+    Abs,
+    Sqrt,
+    Exp,
+    Ln,
+    Log10,
+    Floor,
+    Ceil,
+    Min,
+    Max,
+    Pow,
+    Clamp,
+    Mix,
+}
+
+impl Function {
+    fn parse(name: &str) -> Option<Function> {
+        Some(match name {
+            "sin" => Function::Sin,
+            "cos" => Function::Cos,
+            "tan" => Function::Tan,
+            "abs" => Function::Abs,
+            "sqrt" => Function::Sqrt,
+            "exp" => Function::Exp,
+            "ln" => Function::Ln,
+            "log10" => Function::Log10,
+            "floor" => Function::Floor,
+            "ceil" => Function::Ceil,
+            "min" => Function::Min,
+            "max" => Function::Max,
+            "pow" => Function::Pow,
+            "clamp" => Function::Clamp,
+            "mix" => Function::Mix,
+            _ => return None,
+        })
+    }
+
+    fn arity(self) -> usize {
+        match self {
+            Function::Min | Function::Max | Function::Pow => 2,
+            Function::Clamp | Function::Mix => 3,
+            _ => 1,
+        }
+    }
+}
+
+/// An instruction of the stack machine formulas are compiled to.
+#[derive(Clone, Copy, Debug)]
+enum Instruction {
+    Const(f64),
+    Var(usize),
+    Neg,
+    Binary(char),
+    Compare(Compare),
+    Call(Function),
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+    code: Vec<Instruction>,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<Token> {
+        self.tokens.get(self.position).cloned()
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.peek();
+        self.position += 1;
+        token
+    }
+
+    fn expect(&mut self, expected: Token, what: &str) -> Result<(), String> {
+        match self.next() {
+            Some(token) if token == expected => Ok(()),
+            _ => Err(format!("Expected {}", what)),
+        }
+    }
+
+    /// Comparisons, which bind loosest and give 1 when true and 0 otherwise.
+    fn comparison(&mut self) -> Result<(), String> {
+        self.sum()?;
+
+        let op = match self.peek() {
+            Some(Token::Compare(op)) => op,
+            _ => return Ok(()),
+        };
+
+        self.next();
+        self.sum()?;
+        self.code.push(Instruction::Compare(op));
+
+        Ok(())
+    }
+
+    fn sum(&mut self) -> Result<(), String> {
+        self.product()?;
+
+        while let Some(Token::Op(op)) = self.peek() {
+            if op != '+' && op != '-' {
+                break;
+            }
+
+            self.next();
+            self.product()?;
+            self.code.push(Instruction::Binary(op));
+        }
+
+        Ok(())
+    }
+
+    fn product(&mut self) -> Result<(), String> {
+        self.unary()?;
+
+        while let Some(Token::Op(op)) = self.peek() {
+            if op != '*' && op != '/' && op != '%' {
+                break;
+            }
+
+            self.next();
+            self.unary()?;
+            self.code.push(Instruction::Binary(op));
+        }
+
+        Ok(())
+    }
+
+    fn unary(&mut self) -> Result<(), String> {
+        if self.peek() == Some(Token::Op('-')) {
+            self.next();
+            self.unary()?;
+            self.code.push(Instruction::Neg);
+
+            return Ok(());
+        }
+
+        self.power()
+    }
+
+    /// Powers bind tighter than negation on their left and are right
+    /// associative, so `-a^b^c` is `-(a^(b^c))`.
+    fn power(&mut self) -> Result<(), String> {
+        self.primary()?;
+
+        if self.peek() == Some(Token::Op('^')) {
+            self.next();
+            self.unary()?;
+            self.code.push(Instruction::Binary('^'));
+        }
+
+        Ok(())
+    }
+
+    fn primary(&mut self) -> Result<(), String> {
+        match self.next() {
+            Some(Token::Number(number)) => self.code.push(Instruction::Const(number)),
+            Some(Token::LeftParen) => {
+                self.comparison()?;
+                self.expect(Token::RightParen, "')'")?;
+            }
+            Some(Token::Ident(name)) => {
+                let name = name.as_str();
+
+                if self.peek() == Some(Token::LeftParen) {
+                    return self.call(name);
+                }
+
+                if name == "pi" {
+                    self.code.push(Instruction::Const(std::f64::consts::PI));
+                } else {
+                    let index = VARIABLES
+                        .iter()
+                        .position(|variable| *variable == name)
+                        .ok_or_else(|| format!("Unknown variable {}", name))?;
+
+                    self.code.push(Instruction::Var(index));
+                }
+            }
+            Some(_) => return Err("Expected a value".to_string()),
+            None => return Err("Unexpected end".to_string()),
+        }
+
+        Ok(())
+    }
+
+    fn call(&mut self, name: &str) -> Result<(), String> {
+        let function = Function::parse(name).ok_or_else(|| format!("Unknown function {}", name))?;
+        let arity = function.arity();
+
+        self.expect(Token::LeftParen, "'('")?;
+
+        for arg in 0..arity {
+            if arg > 0 {
+                self.expect(Token::Comma, "','")?;
+            }
+
+            self.comparison()?;
+        }
+
+        self.expect(
+            Token::RightParen,
+            &format!("')', {} takes {} arguments", name, arity),
+        )?;
+        self.code.push(Instruction::Call(function));
+
+        Ok(())
+    }
+}
+
+/// A compiled formula.
+pub struct Program {
+    code: Vec<Instruction>,
+    /// Kept between evaluations so evaluating doesn't allocate.
+    stack: Vec<f64>,
+    /// The most values on the stack while evaluating.
+    max_depth: usize,
+}
+
+// a derived clone would give the stack no capacity, so the copy sent to the
+// audio thread would allocate on its first evaluation
+impl Clone for Program {
+    fn clone(&self) -> Self {
+        Self {
+            code: self.code.clone(),
+            stack: Vec::with_capacity(self.max_depth),
+            max_depth: self.max_depth,
+        }
+    }
+}
+
+impl Program {
+    pub fn compile(source: &str) -> Result<Self, String> {
+        let mut parser = Parser {
+            tokens: tokenize(source)?,
+            position: 0,
+            code: Vec::new(),
+        };
+
+        parser.comparison()?;
+
+        if parser.position < parser.tokens.len() {
+            return Err("Unexpected input after the formula".to_string());
+        }
+
+        let mut depth: usize = 0;
+        let mut max_depth = 0;
+
+        for instruction in &parser.code {
+            depth = match instruction {
+                Instruction::Const(_) | Instruction::Var(_) => depth + 1,
+                Instruction::Neg => depth,
+                Instruction::Binary(_) | Instruction::Compare(_) => depth - 1,
+                Instruction::Call(function) => depth + 1 - function.arity(),
+            };
+
+            max_depth = max_depth.max(depth);
+        }
+
+        Ok(Self {
+            code: parser.code,
+            stack: Vec::with_capacity(max_depth),
+            max_depth,
+        })
+    }
+
+    pub fn eval(&mut self, variables: &[f64; VARIABLES.len()]) -> f64 {
+        let stack = &mut self.stack;
+        stack.clear();
+
+        for instruction in &self.code {
+            let value = match *instruction {
+                Instruction::Const(value) => value,
+                Instruction::Var(index) => variables[index],
+                Instruction::Neg => -stack.pop().unwrap(),
+                Instruction::Binary(op) => {
+                    let b = stack.pop().unwrap();
+                    let a = stack.pop().unwrap();
+
+                    match op {
+                        '+' => a + b,
+                        '-' => a - b,
+                        '*' => a * b,
+                        '/' => a / b,
+                        '%' => a % b,
+                        _ => a.powf(b),
+                    }
+                }
+                Instruction::Compare(op) => {
+                    let b = stack.pop().unwrap();
+                    let a = stack.pop().unwrap();
+
+                    let result = match op {
+                        Compare::Less => a < b,
+                        Compare::Greater => a > b,
+                        Compare::LessEqual => a <= b,
+                        Compare::GreaterEqual => a >= b,
+                        Compare::Equal => a == b,
+                        Compare::NotEqual => a != b,
+                    };
+
+                    if result {
+                        1.0
+                    } else {
+                        0.0
+                    }
+                }
+                Instruction::Call(function) => {
+                    let a = stack.pop().unwrap();
+
+                    match function {
+                        Function::Sin => a.sin(),
+                        Function::Cos => a.cos(),
+                        Function::Tan => a.tan(),
+                        Function::Abs => a.abs(),
+                        Function::Sqrt => a.sqrt(),
+                        Function::Exp => a.exp(),
+                        Function::Ln => a.ln(),
+                        Function::Log10 => a.log10(),
+                        Function::Floor => a.floor(),
+                        Function::Ceil => a.ceil(),
+                        Function::Min => stack.pop().unwrap().min(a),
+                        Function::Max => stack.pop().unwrap().max(a),
+                        Function::Pow => stack.pop().unwrap().powf(a),
+                        Function::Clamp => {
+                            let min = stack.pop().unwrap();
+                            let value = stack.pop().unwrap();
+
+                            value.max(min.min(a)).min(min.max(a))
+                        }
+                        Function::Mix => {
+                            let to = stack.pop().unwrap();
+                            let from = stack.pop().unwrap();
+
+                            from + (to - from) * a
+                        }
+                    }
+                }
+            };
+
+            stack.push(value);
+        }
+
+        stack.pop().unwrap_or(0.0)
+    }
+}
+
+/// Evaluates a formula over its inputs `a` to `d`, the time `t`, `freq` and
+/// the sample rate `sr`.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ExpressionNode {
+    source: String,
+    /// The last formula that compiled, which keeps playing while `source`
+    /// is being edited, also after saving and loading.
+    #[serde(default)]
+    compiled: String,
+    /// Compiled when the formula is edited or loaded.
+    #[serde(skip)]
+    program: Option<Program>,
+    #[serde(skip)]
+    error: Option<String>,
+}
+
+impl ExpressionNode {
+    pub fn new() -> Self {
+        let mut node = Self {
+            source: "sin(t * freq * 2 * pi) * a".to_string(),
+            compiled: String::new(),
+            program: None,
+            error: None,
+        };
+
+        node.compile();
+        node
+    }
+
+    /// Compiles the formula, returns true if it compiled. Otherwise the last
+    /// program that compiled is kept.
+    fn compile(&mut self) -> bool {
+        match Program::compile(&self.source) {
+            Ok(program) => {
+                self.program = Some(program);
+                self.compiled = self.source.clone();
+                self.error = None;
+                true
+            }
+            Err(err) => {
+                self.error = Some(err);
+                false
+            }
+        }
+    }
+}
+
+impl Node for ExpressionNode {
+    fn name(&self) -> &str {
+        "Expression"
+    }
+
+    fn input_slot_types(&self) -> &[(&'static str, SlotType)] {
+        &[
+            ("freq", SlotType::Pitch),
+            ("a", SlotType::Control),
+            ("b", SlotType::Control),
+            ("c", SlotType::Control),
+            ("d", SlotType::Control),
+        ]
+    }
+
+    fn output_slot_types(&self) -> &[(&'static str, SlotType)] {
+        &[("out", SlotType::Control)]
+    }
+
+    fn display_out(&self) -> &Option<&str> {
+        &Some("out")
+    }
+
+    fn run(
+        &mut self,
+        ctx: &NodeCtx,
+        input: HashMap<String, SlotValue>,
+    ) -> Vec<(&'static str, SlotValue)> {
+        let program = match &mut self.program {
+            Some(program) => program,
+            None => return vec![("out", SlotValue::None)],
+        };
+
+        let variables = [
+            input["a"].unwrap_f64(0.0),
+            input["b"].unwrap_f64(0.0),
+            input["c"].unwrap_f64(0.0),
+            input["d"].unwrap_f64(0.0),
+            ctx.time,
+            input["freq"].unwrap_f64(ctx.freq),
+            1.0 / ctx.sample_length,
+        ];
+
+        vec![("out", SlotValue::Float(program.eval(&variables)))]
+    }
+
+    fn ui(&mut self, ui: &mut Ui) -> bool {
+        let mut changed = false;
+
+        ui.vertical(|ui| {
+            ui.set_max_width(200.0);
+
+            // the running formula is only replaced by one that compiles
+            if ui.text_edit_singleline(&mut self.source).changed() {
+                changed = self.compile();
+            }
+
+            if let Some(err) = &self.error {
+                ui.colored_label(Color32::from_rgb(220, 80, 80), err.as_str());
+            }
+        });
+
+        changed
+    }
+
+    /// Compiles the saved formula, or the last one that compiled if it was
+    /// saved while being edited.
+    fn load_resources(&mut self) {
+        if !self.compile() {
+            self.program = Program::compile(&self.compiled).ok();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(source: &str, a: f64) -> f64 {
+        let mut program = Program::compile(source).unwrap();
+
+        program.eval(&[a, 0.0, 0.0, 0.0, 0.0, 440.0, 44100.0])
+    }
+
+    #[test]
+    fn precedence() {
+        assert_eq!(eval("-a^2", 3.0), -9.0);
+        assert_eq!(eval("2^3^2", 0.0), 512.0);
+        assert_eq!(eval("1+2*3", 0.0), 7.0);
+        assert_eq!(eval("(1+2)*3", 0.0), 9.0);
+        assert_eq!(eval("10-4-3", 0.0), 3.0);
+        assert_eq!(eval("7%4*2", 0.0), 6.0);
+    }
+
+    #[test]
+    fn comparisons() {
+        assert_eq!(eval("1+2*3<7", 0.0), 0.0);
+        assert_eq!(eval("1+2*3<=7", 0.0), 1.0);
+        assert_eq!(eval("a>=2", 2.0), 1.0);
+        assert_eq!(eval("a!=2", 2.0), 0.0);
+        assert_eq!(eval("a==2", 2.0), 1.0);
+    }
+
+    #[test]
+    fn functions() {
+        assert_eq!(eval("clamp(a,0,1)", 2.0), 1.0);
+        assert_eq!(eval("clamp(a,0,1)", -1.0), 0.0);
+        assert_eq!(eval("clamp(a,0,1)", 0.5), 0.5);
+        assert_eq!(eval("mix(2,4,a)", 0.25), 2.5);
+        assert_eq!(eval("min(a,1)+max(a,1)", 3.0), 4.0);
+        assert_eq!(eval("pow(2,a)", 3.0), 8.0);
+        assert_eq!(eval("abs(-a)", 3.0), 3.0);
+        assert_eq!(eval("freq/sr*2", 0.0), 440.0 / 44100.0 * 2.0);
+        assert!((eval("sin(pi/2)", 0.0) - 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn stack_capacity() {
+        // each operand is pushed before the multiplication pops two
+        assert!(Program::compile("1+2*3").unwrap().stack.capacity() >= 3);
+        assert!(Program::compile("clamp(a,0,1)").unwrap().stack.capacity() >= 3);
+        assert!(Program::compile("-a").unwrap().stack.capacity() >= 1);
+
+        let program = Program::compile("a*(b+c)").unwrap();
+        assert!(program.clone().stack.capacity() >= 3);
+    }
+
+    #[test]
+    fn arity() {
+        assert!(Program::compile("min(1)").is_err());
+        assert!(Program::compile("clamp(1,2)").is_err());
+        assert!(Program::compile("sin(1,2)").is_err());
+        assert!(Program::compile("mix(1,2,3,4)").is_err());
+    }
+
+    #[test]
+    fn errors() {
+        for source in &[
+            "", "1+", "(1", "1)", "1 2", "foo", "bar(1)", "a $ b", "1..2", "<1",
+        ] {
+            assert!(Program::compile(source).is_err(), "{} compiled", source);
+        }
+    }
+}
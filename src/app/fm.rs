@@ -0,0 +1,148 @@
+use crate::knob::knob;
+use crate::node::*;
+use eframe::egui::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::f64::consts::PI;
+
+/// A sine operator with its own phase, so its phase can be modulated by the
+/// output of other operators. Operators are chained into algorithms by
+/// connecting `out` to the `pm` input of the next one.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct FmOperatorNode {
+    /// Frequency as a multiple of the input frequency.
+    ratio: f64,
+    /// Ignore the input frequency and play `fixed_freq`.
+    fixed: bool,
+    fixed_freq: f64,
+    /// Output level, which is the modulation index in radians when the
+    /// operator modulates another one.
+    index: f64,
+    /// Phase modulation by the operator's own output.
+    feedback: f64,
+    /// Position in the current period from 0 to 1.
+    #[serde(skip)]
+    phase: f64,
+    /// The last two outputs, averaged for feedback to keep it from
+    /// oscillating at the sample rate.
+    #[serde(skip)]
+    history: (f64, f64),
+}
+
+impl FmOperatorNode {
+    pub fn new() -> Self {
+        Self {
+            ratio: 1.0,
+            fixed: false,
+            fixed_freq: 440.0,
+            index: 1.0,
+            feedback: 0.0,
+            phase: 0.0,
+            history: (0.0, 0.0),
+        }
+    }
+}
+
+impl Node for FmOperatorNode {
+    fn name(&self) -> &str {
+        "FM Operator"
+    }
+
+    fn input_slot_types(&self) -> &[(&'static str, SlotType)] {
+        &[
+            ("freq", SlotType::Pitch),
+            ("pm", SlotType::Audio),
+            ("amp", SlotType::Control),
+        ]
+    }
+
+    fn output_slot_types(&self) -> &[(&'static str, SlotType)] {
+        &[("freq_out", SlotType::Pitch), ("out", SlotType::Audio)]
+    }
+
+    fn display_out(&self) -> &Option<&str> {
+        &Some("out")
+    }
+
+    fn setup(&mut self) {
+        self.phase = 0.0;
+        self.history = (0.0, 0.0);
+    }
+
+    fn run(
+        &mut self,
+        ctx: &NodeCtx,
+        input: HashMap<String, SlotValue>,
+    ) -> Vec<(&'static str, SlotValue)> {
+        let freq = input["freq"].unwrap_f64(ctx.freq);
+        let pm = input["pm"].unwrap_f64(0.0);
+        let amp = input["amp"].unwrap_f64(1.0);
+
+        let feedback = self.feedback * PI * (self.history.0 + self.history.1) * 0.5;
+        let out = (self.phase * PI * 2.0 + pm + feedback).sin() * self.index * amp;
+
+        self.history = (out, self.history.0);
+
+        let op_freq = if self.fixed {
+            self.fixed_freq
+        } else {
+            freq * self.ratio
+        };
+
+        self.phase = (self.phase + op_freq * ctx.sample_length).rem_euclid(1.0);
+
+        vec![
+            ("freq_out", SlotValue::Float(freq)),
+            ("out", SlotValue::Float(out)),
+        ]
+    }
+
+    fn ui(&mut self, ui: &mut Ui) -> bool {
+        let prev_fixed = self.fixed;
+
+        ui.vertical(|ui| {
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut self.fixed, "Fixed");
+
+                if self.fixed {
+                    ui.add(DragValue::f64(&mut self.fixed_freq).speed(1.0));
+                    ui.label("Hz");
+                } else {
+                    ui.label("Ratio");
+                    ui.add(DragValue::f64(&mut self.ratio).speed(0.01));
+                }
+            });
+
+            ui.horizontal(|ui| {
+                ui.vertical(|ui| {
+                    ui.label("Index");
+                    knob(ui, &mut self.index, 0.0, 10.0);
+                });
+
+                ui.vertical(|ui| {
+                    ui.label("Feedback");
+                    knob(ui, &mut self.feedback, 0.0, 1.0);
+                });
+            });
+        });
+
+        self.ratio = self.ratio.max(0.0).min(64.0);
+        self.fixed_freq = self.fixed_freq.max(0.0).min(20000.0);
+
+        self.fixed != prev_fixed
+    }
+
+    fn params(&self) -> Vec<f64> {
+        vec![self.ratio, self.fixed_freq, self.index, self.feedback]
+    }
+
+    fn set_param(&mut self, index: usize, value: f64) {
+        match index {
+            0 => self.ratio = value,
+            1 => self.fixed_freq = value,
+            2 => self.index = value,
+            3 => self.feedback = value,
+            _ => {}
+        }
+    }
+}
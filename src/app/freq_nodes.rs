@@ -4,7 +4,7 @@ use crate::node::SlotValue;
 pub struct InputFreqNode;
 
 crate::node! {
-    InputFreqNode => "Input Freq"(&mut self, ctx: &NodeCtx) -> [out: Float] {
+    InputFreqNode => "Input Freq"(&mut self, ctx: &NodeCtx) -> [out: Pitch] {
         out = SlotValue::Float(ctx.freq);
     }
 
@@ -23,11 +23,59 @@ impl FreqShiftNode {
 }
 
 crate::node! {
-    FreqShiftNode => "Freq Shift"(&mut self, ctx: &NodeCtx, freq: Float) -> [freq_out: Float] {
+    FreqShiftNode => "Freq Shift"(&mut self, ctx: &NodeCtx, freq: Pitch) -> [freq_out: Pitch] {
         let freq = freq.unwrap_f64(ctx.freq) * 2.0f64.powf(self.0 / 12.0);
         freq_out = SlotValue::Float(freq);
     }
 
+    fn params(&self) -> Vec<f64> {
+        vec![self.0]
+    }
+
+    fn set_param(&mut self, index: usize, value: f64) {
+        if index == 0 {
+            self.0 = value;
+        }
+    }
+
+    fn ui(&mut self, ui: &mut Ui) -> bool {
+        let prev = self.0;
+
+        ui.vertical(|ui| {
+            ui.set_max_width(100.0);
+            ui.add(eframe::egui::DragValue::f64(&mut self.0));
+        });
+
+        self.0 != prev
+    }
+}
+
+/// Shifts a frequency by steps of the tuning's scale rather than semitones.
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+pub struct ScaleShiftNode(f64);
+
+impl ScaleShiftNode {
+    pub fn new() -> Self {
+        Self(0.0)
+    }
+}
+
+crate::node! {
+    ScaleShiftNode => "Scale Shift"(&mut self, ctx: &NodeCtx, freq: Pitch) -> [freq_out: Pitch] {
+        let note = ctx.tuning.freq_to_note(freq.unwrap_f64(ctx.freq));
+        freq_out = SlotValue::Float(ctx.tuning.note_to_freq(note + self.0));
+    }
+
+    fn params(&self) -> Vec<f64> {
+        vec![self.0]
+    }
+
+    fn set_param(&mut self, index: usize, value: f64) {
+        if index == 0 {
+            self.0 = value;
+        }
+    }
+
     fn ui(&mut self, ui: &mut Ui) -> bool {
         let prev = self.0;
 
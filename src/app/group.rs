@@ -0,0 +1,721 @@
+use crate::dsp::*;
+use crate::node::*;
+use eframe::egui::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+pub const MAX_PORTS: usize = 8;
+
+pub const INPUT_PORTS: [&str; MAX_PORTS] = [
+    "in 1", "in 2", "in 3", "in 4", "in 5", "in 6", "in 7", "in 8",
+];
+
+pub const OUTPUT_PORTS: [&str; MAX_PORTS] = [
+    "out 1", "out 2", "out 3", "out 4", "out 5", "out 6", "out 7", "out 8",
+];
+
+/// Ports are stored as their types only, the names are given by their position.
+macro_rules! ports_serde {
+    ($module:ident, $names:ident) => {
+        mod $module {
+            use super::*;
+            use serde::{Deserializer, Serializer};
+
+            pub fn serialize<S: Serializer>(
+                ports: &[(&'static str, SlotType)],
+                serializer: S,
+            ) -> Result<S::Ok, S::Error> {
+                ports
+                    .iter()
+                    .map(|(_, ty)| *ty)
+                    .collect::<Vec<_>>()
+                    .serialize(serializer)
+            }
+
+            pub fn deserialize<'de, D: Deserializer<'de>>(
+                deserializer: D,
+            ) -> Result<Vec<(&'static str, SlotType)>, D::Error> {
+                let types = Vec::<SlotType>::deserialize(deserializer)?;
+
+                Ok($names.iter().cloned().zip(types).collect())
+            }
+        }
+    };
+}
+
+ports_serde!(input_ports, INPUT_PORTS);
+ports_serde!(output_ports, OUTPUT_PORTS);
+
+/// Exposes the inputs of a group inside its subpatch. The values are
+/// seeded by the group before the subpatch is run.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct GroupInputNode {
+    #[serde(with = "input_ports")]
+    pub ports: Vec<(&'static str, SlotType)>,
+}
+
+impl Node for GroupInputNode {
+    fn name(&self) -> &str {
+        "Group Input"
+    }
+
+    fn input_slot_types(&self) -> &[(&'static str, SlotType)] {
+        &[]
+    }
+
+    fn output_slot_types(&self) -> &[(&'static str, SlotType)] {
+        &self.ports
+    }
+
+    fn run(
+        &mut self,
+        _ctx: &NodeCtx,
+        _input: HashMap<String, SlotValue>,
+    ) -> Vec<(&'static str, SlotValue)> {
+        self.ports
+            .iter()
+            .map(|(name, _)| (*name, SlotValue::None))
+            .collect()
+    }
+
+    fn ui(&mut self, _ui: &mut Ui) -> bool {
+        false
+    }
+}
+
+/// Collects the outputs of a group inside its subpatch.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct GroupOutputNode {
+    #[serde(with = "output_ports")]
+    pub ports: Vec<(&'static str, SlotType)>,
+}
+
+impl Node for GroupOutputNode {
+    fn name(&self) -> &str {
+        "Group Output"
+    }
+
+    fn input_slot_types(&self) -> &[(&'static str, SlotType)] {
+        &self.ports
+    }
+
+    fn output_slot_types(&self) -> &[(&'static str, SlotType)] {
+        &[]
+    }
+
+    fn run(
+        &mut self,
+        _ctx: &NodeCtx,
+        input: HashMap<String, SlotValue>,
+    ) -> Vec<(&'static str, SlotValue)> {
+        self.ports
+            .iter()
+            .map(|(name, _)| (*name, input[*name]))
+            .collect()
+    }
+
+    fn ui(&mut self, _ui: &mut Ui) -> bool {
+        false
+    }
+}
+
+/// Delays values that are held rather than resampled by whole samples,
+/// keeping them in step with the resampled ports.
+#[derive(Clone)]
+struct SlotDelay {
+    values: Vec<SlotValue>,
+    pos: usize,
+    /// Filled with the first value, so connected ports don't start as
+    /// unconnected.
+    primed: bool,
+}
+
+impl SlotDelay {
+    fn new(delay: f64) -> Self {
+        Self {
+            values: vec![SlotValue::None; delay.round() as usize],
+            pos: 0,
+            primed: false,
+        }
+    }
+
+    fn process(&mut self, value: SlotValue) -> SlotValue {
+        if self.values.is_empty() {
+            return value;
+        }
+
+        if !self.primed {
+            self.values.iter_mut().for_each(|delayed| *delayed = value);
+            self.primed = true;
+        }
+
+        let delayed = std::mem::replace(&mut self.values[self.pos], value);
+        self.pos = (self.pos + 1) % self.values.len();
+
+        delayed
+    }
+}
+
+/// The state of an oversampled group: resamplers for the left and right
+/// channels of each port, delays for the ports that aren't resampled, and
+/// buffers reused every sample.
+#[derive(Clone)]
+struct PortResamplers {
+    factor: usize,
+    inputs: Vec<[Oversampler; 2]>,
+    outputs: Vec<[Oversampler; 2]>,
+    /// Held inputs are delayed by the latency of upsampling, held outputs
+    /// by that of downsampling.
+    input_delays: Vec<SlotDelay>,
+    output_delays: Vec<SlotDelay>,
+    held_inputs: Vec<SlotValue>,
+    /// Samples of each port and channel for the steps of one sample.
+    upsampled: Vec<[[f64; MAX_OVERSAMPLING]; 2]>,
+    results: Vec<[[f64; MAX_OVERSAMPLING]; 2]>,
+    last_outputs: Vec<SlotValue>,
+    /// The inputs of the subpatch in each step.
+    sub_input: HashMap<String, SlotValue>,
+}
+
+impl PortResamplers {
+    fn new(
+        factor: usize,
+        inputs: &[(&'static str, SlotType)],
+        outputs: &[(&'static str, SlotType)],
+    ) -> Self {
+        let pair = || [Oversampler::new(factor), Oversampler::new(factor)];
+
+        Self {
+            factor,
+            inputs: inputs.iter().map(|_| pair()).collect(),
+            outputs: outputs.iter().map(|_| pair()).collect(),
+            input_delays: inputs
+                .iter()
+                .map(|_| SlotDelay::new(Oversampler::upsample_latency(factor)))
+                .collect(),
+            output_delays: outputs
+                .iter()
+                .map(|_| SlotDelay::new(Oversampler::downsample_latency(factor)))
+                .collect(),
+            held_inputs: vec![SlotValue::None; inputs.len()],
+            upsampled: vec![[[0.0; MAX_OVERSAMPLING]; 2]; inputs.len()],
+            results: vec![[[0.0; MAX_OVERSAMPLING]; 2]; outputs.len()],
+            last_outputs: vec![SlotValue::None; outputs.len()],
+            sub_input: inputs
+                .iter()
+                .map(|(name, _)| (name.to_string(), SlotValue::None))
+                .collect(),
+        }
+    }
+
+    fn matches(&self, factor: usize, inputs: usize, outputs: usize) -> bool {
+        self.factor == factor && self.inputs.len() == inputs && self.outputs.len() == outputs
+    }
+}
+
+fn default_oversampling() -> usize {
+    1
+}
+
+/// A node that encapsulates a subpatch, with the ports of the subpatch's
+/// input and output nodes as its slots.
+///
+/// The subpatch can run at a multiple of the sample rate, with audio ports
+/// resampled and other ports held, to reduce aliasing in the nodes inside.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct GroupNode {
+    pub label: String,
+    pub nodes: NodeManager,
+    #[serde(with = "input_ports")]
+    pub inputs: Vec<(&'static str, SlotType)>,
+    #[serde(with = "output_ports")]
+    pub outputs: Vec<(&'static str, SlotType)>,
+    /// Nodes in the subpatch whose controls are shown on the group.
+    pub exposed: Vec<NodeId>,
+    pub collapsed: bool,
+    /// Times the sample rate the subpatch runs at.
+    #[serde(default = "default_oversampling")]
+    pub oversampling: usize,
+    #[serde(skip)]
+    resamplers: Option<PortResamplers>,
+}
+
+impl GroupNode {
+    pub fn new() -> Self {
+        let mut group = Self {
+            label: String::from("Group"),
+            nodes: NodeManager::new(),
+            inputs: vec![(INPUT_PORTS[0], SlotType::Audio)],
+            outputs: vec![(OUTPUT_PORTS[0], SlotType::Audio)],
+            exposed: Vec::new(),
+            collapsed: false,
+            oversampling: 1,
+            resamplers: None,
+        };
+
+        group.update_ports();
+        group.nodes.auto_layout();
+
+        group
+    }
+
+    /// An empty group running at `factor` times the sample rate, for
+    /// wrapping nonlinear nodes.
+    pub fn oversampled(factor: usize) -> Self {
+        let mut group = Self::new();
+
+        group.label = String::from("Oversampler");
+        group.oversampling = factor;
+        group.load_resources();
+
+        group
+    }
+
+    /// A group running `node` at `factor` times the sample rate, with a port
+    /// for each of its slots. Returns `None` if it has too many slots.
+    pub fn wrapping(mut node: NodeContainer, factor: usize) -> Option<Self> {
+        let node_inputs = node.inner.input_slot_types().to_vec();
+        let node_outputs = node.inner.output_slot_types().to_vec();
+
+        if node_inputs.len() > MAX_PORTS || node_outputs.len() > MAX_PORTS {
+            return None;
+        }
+
+        let mut group = Self::oversampled(factor);
+        group.label = format!("{} ({}x)", node.inner.name(), factor);
+        group.inputs = INPUT_PORTS
+            .iter()
+            .cloned()
+            .zip(node_inputs.iter().map(|(_, ty)| *ty))
+            .collect();
+        group.outputs = OUTPUT_PORTS
+            .iter()
+            .cloned()
+            .zip(node_outputs.iter().map(|(_, ty)| *ty))
+            .collect();
+        group.update_ports();
+
+        node.connections.clear();
+        node.conversions.clear();
+
+        let id = group.nodes.add(node);
+        let (input_node, output_node) = (group.nodes.input_node, group.nodes.output_node);
+
+        for ((port, _), (slot, _)) in group.inputs.iter().zip(&node_inputs) {
+            group.nodes.connect(input_node, port, id, slot);
+        }
+
+        for ((port, _), (slot, _)) in group.outputs.iter().zip(&node_outputs) {
+            group.nodes.connect(id, slot, output_node, port);
+        }
+
+        group.exposed = vec![id];
+        group.nodes.auto_layout();
+        group.load_resources();
+
+        Some(group)
+    }
+
+    /// The output ports' values in `outputs` of the subpatch.
+    fn port_values(
+        &self,
+        outputs: &HashMap<(NodeId, &'static str), SlotValue>,
+    ) -> Vec<(&'static str, SlotValue)> {
+        let output_node = self.nodes.output_node;
+
+        self.outputs
+            .iter()
+            .map(|(name, _)| {
+                let value = outputs
+                    .get(&(output_node, *name))
+                    .cloned()
+                    .unwrap_or(SlotValue::None);
+
+                (*name, value)
+            })
+            .collect()
+    }
+
+    /// Runs the subpatch `oversampling` times, with the audio inputs
+    /// upsampled and the audio outputs filtered back down.
+    fn run_oversampled(
+        &mut self,
+        ctx: &NodeCtx,
+        input: HashMap<String, SlotValue>,
+    ) -> Vec<(&'static str, SlotValue)> {
+        let factor = self.oversampling.min(MAX_OVERSAMPLING);
+
+        // created on the UI thread, unless the ports changed since
+        let mut state = match self.resamplers.take() {
+            Some(state) if state.matches(factor, self.inputs.len(), self.outputs.len()) => state,
+            _ => PortResamplers::new(factor, &self.inputs, &self.outputs),
+        };
+
+        for (i, (name, ty)) in self.inputs.iter().enumerate() {
+            let value = input[*name];
+            let (left, right) = value.unwrap_stereo(0.0);
+
+            match ty {
+                SlotType::Audio => state.inputs[i][0].upsample(left, &mut state.upsampled[i][0]),
+                SlotType::Stereo => {
+                    state.inputs[i][0].upsample(left, &mut state.upsampled[i][0]);
+                    state.inputs[i][1].upsample(right, &mut state.upsampled[i][1]);
+                }
+                _ => state.held_inputs[i] = state.input_delays[i].process(value),
+            }
+        }
+
+        let mut sub_ctx = ctx.clone();
+        sub_ctx.sample_length = ctx.sample_length / factor as f64;
+
+        let output_node = self.nodes.output_node;
+
+        for step in 0..factor {
+            sub_ctx.time = ctx.time + step as f64 * sub_ctx.sample_length;
+
+            for (i, (name, ty)) in self.inputs.iter().enumerate() {
+                let value = match (input[*name], ty) {
+                    (SlotValue::None, _) => SlotValue::None,
+                    (_, SlotType::Audio) => SlotValue::Float(state.upsampled[i][0][step]),
+                    (_, SlotType::Stereo) => {
+                        SlotValue::Stereo(state.upsampled[i][0][step], state.upsampled[i][1][step])
+                    }
+                    _ => state.held_inputs[i],
+                };
+
+                if let Some(slot) = state.sub_input.get_mut(*name) {
+                    *slot = value;
+                }
+            }
+
+            let outputs = self.nodes.run_with_inputs(&sub_ctx, &state.sub_input);
+
+            for (i, (name, _)) in self.outputs.iter().enumerate() {
+                let value = outputs
+                    .get(&(output_node, *name))
+                    .cloned()
+                    .unwrap_or(SlotValue::None);
+                let (left, right) = value.unwrap_stereo(0.0);
+
+                state.results[i][0][step] = left;
+                state.results[i][1][step] = right;
+                state.last_outputs[i] = value;
+            }
+        }
+
+        let values =
+            self.outputs
+                .iter()
+                .enumerate()
+                .map(|(i, (name, ty))| {
+                    let value = match (state.last_outputs[i], ty) {
+                        (SlotValue::None, SlotType::Audio)
+                        | (SlotValue::None, SlotType::Stereo) => SlotValue::None,
+                        (_, SlotType::Audio) => SlotValue::Float(
+                            state.outputs[i][0].downsample(&state.results[i][0][..factor]),
+                        ),
+                        (_, SlotType::Stereo) => SlotValue::Stereo(
+                            state.outputs[i][0].downsample(&state.results[i][0][..factor]),
+                            state.outputs[i][1].downsample(&state.results[i][1][..factor]),
+                        ),
+                        // other signals keep their last value
+                        (value, _) => state.output_delays[i].process(value),
+                    };
+
+                    (*name, value)
+                })
+                .collect();
+
+        self.resamplers = Some(state);
+
+        values
+    }
+
+    /// Replaces the input and output nodes of the subpatch to match the
+    /// ports of the group, dropping connections to ports that were removed
+    /// or no longer have a compatible type.
+    pub fn update_ports(&mut self) {
+        let input_node = self.nodes.input_node;
+        let output_node = self.nodes.output_node;
+
+        if let Some(node) = self.nodes.nodes.get_mut(&input_node) {
+            node.inner = Box::new(GroupInputNode {
+                ports: self.inputs.clone(),
+            });
+        }
+
+        if let Some(node) = self.nodes.nodes.get_mut(&output_node) {
+            node.inner = Box::new(GroupOutputNode {
+                ports: self.outputs.clone(),
+            });
+        }
+
+        self.nodes.remove_invalid_connections();
+    }
+
+    fn ports_ui(
+        ui: &mut Ui,
+        label: &str,
+        ports: &mut Vec<(&'static str, SlotType)>,
+        names: &[&'static str],
+    ) -> bool {
+        let mut changed = false;
+
+        ui.horizontal(|ui| {
+            ui.label(format!("{}: {}", label, ports.len()));
+
+            if ui.button("+").clicked() && ports.len() < MAX_PORTS {
+                ports.push((names[ports.len()], SlotType::Audio));
+                changed = true;
+            }
+
+            if ui.button("-").clicked() && !ports.is_empty() {
+                ports.pop();
+                changed = true;
+            }
+        });
+
+        // clicking a port cycles through the slot types
+        for (name, ty) in ports.iter_mut() {
+            ui.horizontal(|ui| {
+                ui.label(*name);
+
+                if ui.button(ty.name()).clicked() {
+                    let i = SlotType::ALL.iter().position(|t| t == ty).unwrap_or(0);
+                    *ty = SlotType::ALL[(i + 1) % SlotType::ALL.len()];
+                    changed = true;
+                }
+            });
+        }
+
+        changed
+    }
+}
+
+impl Node for GroupNode {
+    fn name(&self) -> &str {
+        &self.label
+    }
+
+    fn input_slot_types(&self) -> &[(&'static str, SlotType)] {
+        &self.inputs
+    }
+
+    fn output_slot_types(&self) -> &[(&'static str, SlotType)] {
+        &self.outputs
+    }
+
+    fn display_out(&self) -> &Option<&str> {
+        if self.outputs.is_empty() {
+            &None
+        } else {
+            &Some("out 1")
+        }
+    }
+
+    fn run(
+        &mut self,
+        ctx: &NodeCtx,
+        input: HashMap<String, SlotValue>,
+    ) -> Vec<(&'static str, SlotValue)> {
+        if self.oversampling > 1 {
+            return self.run_oversampled(ctx, input);
+        }
+
+        let outputs = self.nodes.run_with_inputs(ctx, &input);
+
+        self.port_values(&outputs)
+    }
+
+    fn ui(&mut self, ui: &mut Ui) -> bool {
+        let mut changed = false;
+
+        ui.vertical(|ui| {
+            ui.set_max_width(200.0);
+
+            ui.checkbox(&mut self.collapsed, "Collapsed");
+
+            if !self.collapsed {
+                ui.text_edit_singleline(&mut self.label);
+
+                let mut ports_changed =
+                    Self::ports_ui(ui, "Inputs", &mut self.inputs, &INPUT_PORTS);
+                ports_changed = Self::ports_ui(ui, "Outputs", &mut self.outputs, &OUTPUT_PORTS)
+                    || ports_changed;
+
+                if ports_changed {
+                    self.update_ports();
+                    changed = true;
+                }
+
+                ui.horizontal(|ui| {
+                    let prev = self.oversampling;
+
+                    ui.label("Oversampling");
+
+                    for factor in &OVERSAMPLING {
+                        ui.radio_value(&mut self.oversampling, *factor, format!("{}x", factor));
+                    }
+
+                    changed = changed || self.oversampling != prev;
+                });
+
+                // audio ports are delayed by the resampling filters, and
+                // the others with them
+                if self.oversampling > 1 {
+                    let latency = Oversampler::new(self.oversampling).latency();
+                    ui.label(format!("Latency: {:.1} samples", latency));
+                }
+
+                if changed {
+                    self.load_resources();
+                }
+
+                ui.label("Exposed controls:");
+
+                let mut ids = self.nodes.nodes.keys().cloned().collect::<Vec<_>>();
+                ids.sort_by_key(|id| id.0);
+
+                for id in ids {
+                    if id == self.nodes.input_node || id == self.nodes.output_node {
+                        continue;
+                    }
+
+                    let mut exposed = self.exposed.contains(&id);
+                    let name = self.nodes.nodes[&id].inner.name().to_string();
+
+                    ui.checkbox(&mut exposed, name);
+
+                    if exposed && !self.exposed.contains(&id) {
+                        self.exposed.push(id);
+                    } else if !exposed {
+                        self.exposed.retain(|e| *e != id);
+                    }
+                }
+            }
+
+            for id in &self.exposed {
+                if let Some(node) = self.nodes.nodes.get_mut(id) {
+                    ui.label(node.inner.name().to_string());
+                    changed = node.inner.ui(ui) || changed;
+                }
+            }
+        });
+
+        changed
+    }
+
+    fn subpatch(&mut self) -> Option<&mut NodeManager> {
+        Some(&mut self.nodes)
+    }
+
+    /// Creates the resamplers for the oversampling factor and ports.
+    fn load_resources(&mut self) {
+        self.resamplers = if self.oversampling > 1 {
+            Some(PortResamplers::new(
+                self.oversampling.min(MAX_OVERSAMPLING),
+                &self.inputs,
+                &self.outputs,
+            ))
+        } else {
+            None
+        };
+    }
+}
+
+/// A group saved for reuse, which can be instantiated any number of times.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Module {
+    pub name: String,
+    pub node: NodeContainer,
+}
+
+impl NodeManager {
+    /// Replaces the node `id` with a group running it at `factor` times the
+    /// sample rate, keeping its connections. Returns true if it was wrapped.
+    pub fn wrap_oversampled(&mut self, id: NodeId, factor: usize) -> bool {
+        if id == self.input_node || id == self.output_node {
+            return false;
+        }
+
+        let mut node = match self.nodes.get(&id) {
+            Some(node) => node.clone(),
+            None => return false,
+        };
+
+        if node.inner.subpatch().is_some() {
+            return false;
+        }
+
+        let inputs = node.inner.input_slot_types().to_vec();
+        let outputs = node.inner.output_slot_types().to_vec();
+
+        let group = match GroupNode::wrapping(node.clone(), factor) {
+            Some(group) => group,
+            None => return false,
+        };
+
+        let mut wrapped = NodeContainer::new(group);
+        wrapped.position = node.position;
+        wrapped.connections = node
+            .connections
+            .iter()
+            .filter_map(|(input, from)| {
+                let i = inputs.iter().position(|(name, _)| name == input)?;
+
+                Some((INPUT_PORTS[i].to_string(), from.clone()))
+            })
+            .collect();
+
+        self.nodes.insert(id, wrapped);
+
+        // whatever read from the node now reads from the group's ports
+        for other in self.nodes.values_mut() {
+            for (from, output) in other.connections.values_mut() {
+                if *from != id {
+                    continue;
+                }
+
+                if let Some(i) = outputs.iter().position(|(name, _)| name == output) {
+                    *output = OUTPUT_PORTS[i].to_string();
+                }
+            }
+        }
+
+        self.remove_invalid_connections();
+
+        true
+    }
+
+    /// Follows `path` through nested subpatches.
+    pub fn subpatch_at(&mut self, path: &[NodeId]) -> Option<&mut NodeManager> {
+        let mut nodes = self;
+
+        for id in path {
+            nodes = nodes.nodes.get_mut(id)?.inner.subpatch()?;
+        }
+
+        Some(nodes)
+    }
+
+    /// The node at the end of `path`.
+    pub fn container_at(&mut self, path: &[NodeId]) -> Option<&mut NodeContainer> {
+        let (last, parent) = path.split_last()?;
+
+        self.subpatch_at(parent)?.nodes.get_mut(last)
+    }
+
+    /// Names of the nodes along `path`.
+    pub fn path_names(&mut self, path: &[NodeId]) -> Vec<String> {
+        let mut names = Vec::new();
+
+        for i in 1..=path.len() {
+            match self.container_at(&path[..i]) {
+                Some(node) => names.push(node.inner.name().to_string()),
+                None => break,
+            }
+        }
+
+        names
+    }
+}
@@ -0,0 +1,264 @@
+use crate::device::*;
+use crate::node::*;
+use cpal::{traits::*, *};
+use crossbeam::queue::ArrayQueue;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+/// Frames buffered between the input and output streams.
+const INPUT_QUEUE_SIZE: usize = 1 << 13;
+
+/// Frames allowed to build up in the queue before the oldest are dropped,
+/// enough for the buffers of both streams while keeping the latency low.
+const INPUT_MAX_FILL: usize = 2048;
+
+/// Where the audio input node reads from.
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+pub enum InputSource {
+    None,
+    /// An input device by name, the default device if `None`.
+    Device(Option<String>),
+    /// A WAV file, played in a loop.
+    File(String),
+}
+
+impl Default for InputSource {
+    fn default() -> Self {
+        InputSource::None
+    }
+}
+
+/// The audio input as seen by the driver, read a frame at a time.
+pub enum AudioInput {
+    None,
+    /// Frames written by an input stream.
+    Device(Arc<ArrayQueue<(f32, f32)>>),
+    File {
+        frames: Arc<Vec<(f32, f32)>>,
+        /// Frames of the file per output frame.
+        step: f64,
+        position: f64,
+    },
+}
+
+impl AudioInput {
+    /// Opens `source` for an output running at `sample_rate`. The returned
+    /// stream has to be kept alive for device input.
+    pub fn open(
+        source: &InputSource,
+        host: &Host,
+        sample_rate: u32,
+    ) -> Result<(Self, Option<Stream>), anyhow::Error> {
+        match source {
+            InputSource::None => Ok((AudioInput::None, None)),
+            InputSource::Device(name) => {
+                let (queue, stream) = open_device(host, name, sample_rate)?;
+                Ok((AudioInput::Device(queue), Some(stream)))
+            }
+            InputSource::File(path) => {
+                let (frames, file_rate) = read_wav(path)?;
+
+                let input = AudioInput::File {
+                    frames: Arc::new(frames),
+                    step: file_rate as f64 / sample_rate as f64,
+                    position: 0.0,
+                };
+
+                Ok((input, None))
+            }
+        }
+    }
+
+    /// The next frame, silence if the input has nothing for it.
+    pub fn next(&mut self) -> (f64, f64) {
+        match self {
+            AudioInput::None => (0.0, 0.0),
+            AudioInput::Device(queue) => {
+                // a queue that filled up, for instance while the output was
+                // restarting, would otherwise delay the input for good
+                while queue.len() > INPUT_MAX_FILL {
+                    let _ = queue.pop();
+                }
+
+                queue
+                    .pop()
+                    .map_or((0.0, 0.0), |(left, right)| (left as f64, right as f64))
+            }
+            AudioInput::File {
+                frames,
+                step,
+                position,
+            } => {
+                if frames.is_empty() {
+                    return (0.0, 0.0);
+                }
+
+                // linear interpolation, as the file rate may differ from the output
+                let index = *position as usize;
+                let fract = (*position - index as f64) as f32;
+
+                let (a_left, a_right) = frames[index];
+                let (b_left, b_right) = frames[(index + 1) % frames.len()];
+
+                *position = (*position + *step) % frames.len() as f64;
+
+                (
+                    (a_left + (b_left - a_left) * fract) as f64,
+                    (a_right + (b_right - a_right) * fract) as f64,
+                )
+            }
+        }
+    }
+}
+
+/// Opens the input of `settings`, returning silence and a note for the
+/// status line if it fails.
+pub fn open_input(
+    settings: &AudioSettings,
+    sample_rate: u32,
+) -> (AudioInput, Option<Stream>, String) {
+    match AudioInput::open(&settings.input, &find_host(settings), sample_rate) {
+        Ok((input, stream)) => (input, stream, String::new()),
+        Err(err) => (AudioInput::None, None, format!(", input failed: {}", err)),
+    }
+}
+
+fn open_device(
+    host: &Host,
+    name: &Option<String>,
+    sample_rate: u32,
+) -> Result<(Arc<ArrayQueue<(f32, f32)>>, Stream), anyhow::Error> {
+    let device = match name {
+        Some(name) => host
+            .input_devices()?
+            .find(|device| device.name().ok().as_ref() == Some(name)),
+        None => host.default_input_device(),
+    }
+    .ok_or_else(|| anyhow::anyhow!("Input device not found"))?;
+
+    // the input has to run at the rate of the output, or it would be played
+    // back at the wrong speed, preferably with its default channels
+    let channels = device.default_input_config()?.channels();
+
+    let mut ranges: Vec<_> = device
+        .supported_input_configs()?
+        .filter(|range| {
+            range.min_sample_rate().0 <= sample_rate && range.max_sample_rate().0 >= sample_rate
+        })
+        .collect();
+
+    ranges.sort_by_key(|range| range.channels() != channels);
+
+    let supported = ranges
+        .into_iter()
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("Input device doesn't support {} Hz", sample_rate))?
+        .with_sample_rate(SampleRate(sample_rate));
+
+    let config = supported.config();
+    let queue = Arc::new(ArrayQueue::new(INPUT_QUEUE_SIZE));
+
+    let stream = match supported.sample_format() {
+        SampleFormat::F32 => run_input::<f32>(&device, &config, queue.clone())?,
+        SampleFormat::I16 => run_input::<i16>(&device, &config, queue.clone())?,
+        SampleFormat::U16 => run_input::<u16>(&device, &config, queue.clone())?,
+    };
+
+    Ok((queue, stream))
+}
+
+fn run_input<T: Sample>(
+    device: &Device,
+    config: &StreamConfig,
+    queue: Arc<ArrayQueue<(f32, f32)>>,
+) -> Result<Stream, anyhow::Error> {
+    let channels = config.channels as usize;
+
+    let stream = device.build_input_stream(
+        config,
+        move |data: &[T], _: &InputCallbackInfo| {
+            for frame in data.chunks(channels) {
+                let left = frame[0].to_f32();
+                let right = frame.get(1).map_or(left, |sample| sample.to_f32());
+
+                // frames are dropped if the output isn't keeping up
+                let _ = queue.push((left, right));
+            }
+        },
+        |err| println!("Input error: {}", err),
+    )?;
+
+    stream.play()?;
+
+    Ok(stream)
+}
+
+/// Reads a WAV file as stereo frames, returning them with the sample rate.
+pub fn read_wav(path: &str) -> Result<(Vec<(f32, f32)>, u32), anyhow::Error> {
+    let mut reader = hound::WavReader::open(path)?;
+    let spec = reader.spec();
+
+    let samples = match spec.sample_format {
+        hound::SampleFormat::Float => reader.samples::<f32>().collect::<Result<Vec<_>, _>>()?,
+        hound::SampleFormat::Int => {
+            let scale = 1.0 / (1u64 << (spec.bits_per_sample - 1)) as f32;
+
+            reader
+                .samples::<i32>()
+                .map(|sample| sample.map(|sample| sample as f32 * scale))
+                .collect::<Result<Vec<_>, _>>()?
+        }
+    };
+
+    let channels = spec.channels as usize;
+
+    let frames = samples
+        .chunks(channels)
+        .map(|frame| (frame[0], *frame.get(1).unwrap_or(&frame[0])))
+        .collect();
+
+    Ok((frames, spec.sample_rate))
+}
+
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+pub struct AudioInputNode {
+    gain: f64,
+}
+
+impl AudioInputNode {
+    pub fn new() -> Self {
+        Self { gain: 1.0 }
+    }
+}
+
+crate::node! {
+    AudioInputNode => "Audio Input"(&mut self, ctx: &NodeCtx) -> [out: Stereo, mono: Audio] {
+        let (left, right) = ctx.audio_in;
+
+        out = SlotValue::Stereo(left * self.gain, right * self.gain);
+        mono = SlotValue::Float((left + right) * 0.5 * self.gain);
+    }
+
+    display mono;
+
+    fn params(&self) -> Vec<f64> {
+        vec![self.gain]
+    }
+
+    fn set_param(&mut self, index: usize, value: f64) {
+        if index == 0 {
+            self.gain = value;
+        }
+    }
+
+    fn ui(&mut self, ui: &mut Ui) -> bool {
+        let prev = self.gain;
+
+        ui.vertical(|ui| {
+            ui.label("Gain");
+            crate::knob::knob(ui, &mut self.gain, 0.0, 4.0);
+        });
+
+        self.gain != prev
+    }
+}
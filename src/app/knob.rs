@@ -40,3 +40,15 @@ pub fn knob(ui: &mut Ui, value: &mut f64, min: f64, max: f64) -> bool {
 
     changed
 }
+
+/// A knob with its label above it.
+pub fn labelled_knob(ui: &mut Ui, label: &str, value: &mut f64, min: f64, max: f64) -> bool {
+    let mut changed = false;
+
+    ui.vertical(|ui| {
+        ui.label(label);
+        changed = knob(ui, value, min, max);
+    });
+
+    changed
+}
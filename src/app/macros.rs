@@ -12,6 +12,12 @@ macro_rules! node {
 
     $(display $should_display:ident;)?
 
+    $(
+        fn params(&$params_self:ident) -> Vec<f64> $params:block
+
+        fn set_param(&mut $set_self:ident, $index:ident: usize, $value:ident: f64) $set_param:block
+    )?
+
     fn ui(&mut $self:ident, $ui_param:ident: &mut Ui) -> bool $ui:block
 ) => {
         impl $crate::node::Node for $ident {
@@ -39,9 +45,15 @@ macro_rules! node {
                 }
             )?
 
+            $(
+                fn params(&$params_self) -> Vec<f64> $params
+
+                fn set_param(&mut $set_self, $index: usize, $value: f64) $set_param
+            )?
+
             #[allow(unused_variables)]
             fn run(
-                &$self0,
+                &mut $self0,
                 $ctx: &$crate::node::NodeCtx,
                 input: std::collections::HashMap<String, $crate::node::SlotValue>,
             ) -> Vec<(&'static str, $crate::node::SlotValue)> {
@@ -1,4 +1,5 @@
-use crate::node::SlotValue;
+use crate::dsp::{from_db, to_db};
+use crate::node::{NodeCtx, SlotValue};
 
 #[derive(Clone, serde::Serialize, serde::Deserialize, PartialEq)]
 pub enum MathMode {
@@ -6,49 +7,196 @@ pub enum MathMode {
     Sub,
     Mul,
     Div,
+    Min,
+    Max,
+    Abs,
+    Pow,
+    /// `a` limited to `b..c`.
+    Clamp,
+    /// From `a` to `b` by `c`.
+    Mix,
+    /// `a` from the input range to the output range.
+    MapRange,
+    Greater,
+    Less,
+    Equal,
+    Sin,
+    Cos,
+    Tan,
+    DbToLinear,
+    LinearToDb,
+    /// A note number of the tuning to a frequency.
+    NoteToHz,
+    HzToNote,
+    /// Semitones to a frequency ratio.
+    SemitonesToRatio,
+}
+
+impl MathMode {
+    pub const ALL: [MathMode; 22] = [
+        MathMode::Add,
+        MathMode::Sub,
+        MathMode::Mul,
+        MathMode::Div,
+        MathMode::Min,
+        MathMode::Max,
+        MathMode::Abs,
+        MathMode::Pow,
+        MathMode::Clamp,
+        MathMode::Mix,
+        MathMode::MapRange,
+        MathMode::Greater,
+        MathMode::Less,
+        MathMode::Equal,
+        MathMode::Sin,
+        MathMode::Cos,
+        MathMode::Tan,
+        MathMode::DbToLinear,
+        MathMode::LinearToDb,
+        MathMode::NoteToHz,
+        MathMode::HzToNote,
+        MathMode::SemitonesToRatio,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            MathMode::Add => "Add",
+            MathMode::Sub => "Sub",
+            MathMode::Mul => "Mul",
+            MathMode::Div => "Div",
+            MathMode::Min => "Min",
+            MathMode::Max => "Max",
+            MathMode::Abs => "Abs",
+            MathMode::Pow => "Pow",
+            MathMode::Clamp => "Clamp",
+            MathMode::Mix => "Mix",
+            MathMode::MapRange => "Map range",
+            MathMode::Greater => "a > b",
+            MathMode::Less => "a < b",
+            MathMode::Equal => "a = b",
+            MathMode::Sin => "Sin",
+            MathMode::Cos => "Cos",
+            MathMode::Tan => "Tan",
+            MathMode::DbToLinear => "dB to lin",
+            MathMode::LinearToDb => "Lin to dB",
+            MathMode::NoteToHz => "Note to Hz",
+            MathMode::HzToNote => "Hz to note",
+            MathMode::SemitonesToRatio => "Semis to ratio",
+        }
+    }
+}
+
+fn gate(value: bool) -> f64 {
+    if value {
+        1.0
+    } else {
+        0.0
+    }
 }
 
 #[derive(Clone, serde::Serialize, serde::Deserialize)]
 pub struct MathNode {
     mode: MathMode,
+    /// Input minimum and maximum, then output minimum and maximum.
+    #[serde(default = "default_range")]
+    range: [f64; 4],
+}
+
+fn default_range() -> [f64; 4] {
+    [0.0, 1.0, 0.0, 1.0]
 }
 
 impl MathNode {
     pub fn new() -> Self {
         Self {
             mode: MathMode::Add,
+            range: default_range(),
+        }
+    }
+
+    fn apply(&self, ctx: &NodeCtx, a: f64, b: f64, c: f64) -> f64 {
+        match self.mode {
+            MathMode::Add => a + b,
+            MathMode::Sub => a - b,
+            MathMode::Mul => a * b,
+            MathMode::Div if b == 0.0 => 0.0,
+            MathMode::Div => a / b,
+            MathMode::Min => a.min(b),
+            MathMode::Max => a.max(b),
+            MathMode::Abs => a.abs(),
+            MathMode::Pow => a.powf(b),
+            MathMode::Clamp => a.max(b.min(c)).min(b.max(c)),
+            MathMode::Mix => a + (b - a) * c,
+            MathMode::MapRange => {
+                let [in_min, in_max, out_min, out_max] = self.range;
+
+                if in_max == in_min {
+                    out_min
+                } else {
+                    out_min + (a - in_min) / (in_max - in_min) * (out_max - out_min)
+                }
+            }
+            MathMode::Greater => gate(a > b),
+            MathMode::Less => gate(a < b),
+            MathMode::Equal => gate((a - b).abs() < 1e-9),
+            MathMode::Sin => a.sin(),
+            MathMode::Cos => a.cos(),
+            MathMode::Tan => a.tan(),
+            MathMode::DbToLinear => from_db(a),
+            MathMode::LinearToDb => to_db(a.abs()),
+            MathMode::NoteToHz => ctx.tuning.note_to_freq(a),
+            MathMode::HzToNote => ctx.tuning.freq_to_note(a),
+            MathMode::SemitonesToRatio => 2.0f64.powf(a / 12.0),
         }
     }
 }
 
 crate::node! {
-    MathNode => "Math Node"(&mut self, ctx: &NodeCtx, freq: Float, a: Float, b: Float) -> [freq_out: Float, out: Float] {
+    MathNode => "Math Node"(&mut self, ctx: &NodeCtx, freq: Pitch, a: Control, b: Control, c: Control) -> [freq_out: Pitch, out: Control] {
         freq_out = SlotValue::Float(freq.unwrap_f64(ctx.freq));
 
         let a = a.unwrap_f64(0.0);
         let b = b.unwrap_f64(0.0);
+        let c = c.unwrap_f64(0.0);
 
-        match self.mode {
-            MathMode::Add => out = SlotValue::Float(a + b),
-            MathMode::Sub => out = SlotValue::Float(a - b),
-            MathMode::Mul => out = SlotValue::Float(a * b),
-            MathMode::Div => out = SlotValue::Float(a / b),
-        }
+        out = SlotValue::Float(self.apply(ctx, a, b, c));
     }
 
     display out;
 
+    fn params(&self) -> Vec<f64> {
+        self.range.to_vec()
+    }
+
+    fn set_param(&mut self, index: usize, value: f64) {
+        if let Some(bound) = self.range.get_mut(index) {
+            *bound = value;
+        }
+    }
+
     fn ui(&mut self, ui: &mut Ui) -> bool {
         let prev = self.mode.clone();
 
-        ui.vertical(|ui| {
-            ui.set_max_width(100.0);
-            ui.radio_value(&mut self.mode, MathMode::Add, "Add");
-            ui.radio_value(&mut self.mode, MathMode::Sub, "Sub");
-            ui.radio_value(&mut self.mode, MathMode::Mul, "Mul");
-            ui.radio_value(&mut self.mode, MathMode::Div, "Div");
+        ui.horizontal(|ui| {
+            for column in MathMode::ALL.chunks(8) {
+                ui.vertical(|ui| {
+                    for mode in column {
+                        ui.radio_value(&mut self.mode, mode.clone(), mode.name());
+                    }
+                });
+            }
         });
 
+        if self.mode == MathMode::MapRange {
+            for (label, range) in [("From", 0), ("To", 2)].iter() {
+                ui.horizontal(|ui| {
+                    ui.label(*label);
+                    ui.add(eframe::egui::DragValue::f64(&mut self.range[*range]).speed(0.01));
+                    ui.add(eframe::egui::DragValue::f64(&mut self.range[*range + 1]).speed(0.01));
+                });
+            }
+        }
+
         self.mode != prev
     }
 }
@@ -1,32 +1,69 @@
+pub mod arpeggiator;
+pub mod canvas;
+pub mod device;
 pub mod driver;
+pub mod dsp;
+pub mod dynamics;
+pub mod effect_nodes;
+pub mod expression;
+pub mod fm;
 pub mod freq_nodes;
+pub mod group;
+pub mod input;
 pub mod knob;
 pub mod macros;
 pub mod math_nodes;
 pub mod modulator;
 pub mod node;
+pub mod noise;
 pub mod note;
+pub mod palette;
+pub mod preview;
+pub mod quantizer;
+pub mod sampler;
+pub mod scope;
+pub mod sequencer;
+pub mod transport;
+pub mod tuning;
 pub mod value_node;
 pub mod wave;
+pub mod waveshaper;
 
+use crate::device::*;
 use crate::driver::*;
 use crate::freq_nodes::*;
+use crate::group::*;
 use crate::math_nodes::*;
 use crate::modulator::*;
 use crate::node::*;
+use crate::scope::*;
+use crate::transport::*;
+use crate::tuning::*;
 use crate::value_node::*;
 use crate::wave::*;
 use eframe::{egui::*, epi};
+use std::sync::Arc;
 
 pub struct App {
     visualiser_freq: f64,
     driver: DriverHandle,
     nodes: NodeManager,
+    /// Path through nested groups to the patch shown in the editor.
+    path: Vec<NodeId>,
+    modules: Vec<Module>,
+    scope: Scope,
+    show_scope: bool,
+    audio_settings: AudioSettings,
+    /// Scanned when the audio settings are first shown.
+    devices: Option<DeviceList>,
+    audio_status: String,
+    transport: Transport,
+    tuning: TuningSettings,
 }
 
 impl App {
     pub fn new() -> Result<Self, anyhow::Error> {
-        let driver = Driver::run()?;
+        let driver = Driver::run(AudioSettings::default())?;
 
         let nodes: Vec<Box<dyn Node>> = vec![
             Box::new(SquareWave::new()),
@@ -40,6 +77,7 @@ impl App {
         ];
 
         let mut nodes = NodeManager::from(nodes);
+        nodes.auto_layout();
         nodes.calculate_segments(440.0);
 
         driver.set_nodes(nodes.clone());
@@ -48,8 +86,172 @@ impl App {
             visualiser_freq: 440.0,
             driver,
             nodes,
+            path: Vec::new(),
+            modules: Vec::new(),
+            scope: Scope::new(),
+            show_scope: false,
+            audio_settings: AudioSettings::default(),
+            devices: None,
+            audio_status: String::new(),
+            transport: Transport::default(),
+            tuning: TuningSettings::default(),
         })
     }
+
+    /// The patch currently being edited.
+    fn patch(&mut self) -> &mut NodeManager {
+        if self.nodes.subpatch_at(&self.path).is_none() {
+            self.path.clear();
+        }
+
+        self.nodes.subpatch_at(&self.path).unwrap()
+    }
+
+    fn refresh_previews(&mut self) {
+        let freq = self.visualiser_freq;
+
+        self.patch().calculate_segments(freq);
+
+        if !self.path.is_empty() {
+            self.nodes.calculate_segments(freq);
+        }
+    }
+
+    /// Sends the whole graph to the driver and refreshes the previews.
+    fn graph_changed(&mut self) {
+        // new groups have subpatches with the default tuning
+        self.nodes.set_tuning(self.nodes.tuning.clone());
+        self.refresh_previews();
+        self.driver.set_nodes(self.nodes.clone());
+    }
+
+    /// Sends parameter changes made in the current patch to the driver.
+    fn params_changed(&mut self, changes: Vec<ParamChange>) {
+        for change in changes {
+            self.driver.set_param(self.path.clone(), change);
+        }
+
+        self.refresh_previews();
+    }
+
+    fn tuning_changed(&mut self) {
+        let tuning = Arc::new(self.tuning.tuning.clone());
+
+        self.driver.set_tuning(tuning.clone());
+        self.nodes.set_tuning(tuning);
+        self.refresh_previews();
+    }
+
+    /// A restarted driver has no graph, so everything is sent again.
+    fn handle_driver_events(&mut self) {
+        for event in self.driver.events() {
+            match event {
+                DriverEvent::Started(description) => {
+                    self.audio_status = description;
+
+                    self.driver.set_nodes(self.nodes.clone());
+                    self.driver.set_freq(self.visualiser_freq);
+                    self.driver.set_probe(self.scope.probe.clone());
+
+                    for command in self.transport.sync_commands() {
+                        self.driver.transport(command);
+                    }
+
+                    self.driver.set_tuning(self.nodes.tuning.clone());
+                }
+                DriverEvent::Failed(err) => self.audio_status = err,
+            }
+        }
+    }
+
+    fn audio_ui(&mut self, ui: &mut Ui) {
+        ui.label(&self.audio_status);
+
+        let settings = &self.audio_settings;
+        let devices = self
+            .devices
+            .get_or_insert_with(|| DeviceList::scan(settings));
+
+        if self.audio_settings.ui(ui, devices) {
+            self.driver.restart(self.audio_settings.clone());
+        }
+    }
+
+    fn add_node(&mut self, mut node: NodeContainer) {
+        node.load_resources();
+
+        self.patch().add_at_center(node);
+        self.graph_changed();
+    }
+
+    fn patch_ui(&mut self, ui: &mut Ui) {
+        let names = self.nodes.path_names(&self.path);
+
+        ui.horizontal(|ui| {
+            if ui.button("Root").clicked() {
+                self.path.clear();
+            }
+
+            for (i, name) in names.iter().enumerate() {
+                ui.label(">");
+
+                if ui.button(name).clicked() {
+                    self.path.truncate(i + 1);
+                }
+            }
+        });
+
+        if !self.path.is_empty() && ui.button("Save as module").clicked() {
+            if let Some(node) = self.nodes.container_at(&self.path) {
+                let mut node = node.clone();
+                node.connections.clear();
+                node.conversions.clear();
+
+                self.modules.push(Module {
+                    name: node.inner.name().to_string(),
+                    node,
+                });
+            }
+        }
+    }
+
+    fn add_ui(&mut self, ui: &mut Ui) {
+        for name in palette::NODES {
+            if ui.button(*name).clicked() {
+                if let Some(node) = palette::create(name) {
+                    self.add_node(NodeContainer::from(node));
+                }
+            }
+        }
+
+        if !self.modules.is_empty() {
+            ui.separator();
+            ui.label("Modules");
+        }
+
+        let mut add = None;
+        let mut remove = None;
+
+        for (i, module) in self.modules.iter().enumerate() {
+            ui.horizontal(|ui| {
+                if ui.button(&module.name).clicked() {
+                    add = Some(module.node.clone());
+                }
+
+                if ui.button("x").clicked() {
+                    remove = Some(i);
+                }
+            });
+        }
+
+        if let Some(node) = add {
+            self.add_node(node);
+        }
+
+        if let Some(i) = remove {
+            self.modules.remove(i);
+        }
+    }
 }
 
 impl epi::App for App {
@@ -61,15 +263,61 @@ impl epi::App for App {
         if let Some(nodes_ron) = storage.get_string("nodes") {
             if let Ok(nodes) = serde_json::from_str::<NodeManager>(nodes_ron.as_str()) {
                 self.nodes = nodes;
+                self.nodes.load_resources();
+
+                if self.nodes.needs_layout() {
+                    self.nodes.auto_layout();
+                }
+
                 self.driver.set_nodes(self.nodes.clone());
             }
         }
+
+        if let Some(audio) = storage.get_string("audio") {
+            if let Ok(settings) = serde_json::from_str::<AudioSettings>(audio.as_str()) {
+                if settings != self.audio_settings {
+                    self.driver.restart(settings.clone());
+                }
+
+                self.audio_settings = settings;
+            }
+        }
+
+        if let Some(transport) = storage.get_string("transport") {
+            if let Ok(transport) = serde_json::from_str::<Transport>(transport.as_str()) {
+                self.transport = transport;
+
+                for command in self.transport.sync_commands() {
+                    self.driver.transport(command);
+                }
+            }
+        }
+
+        if let Some(tuning) = storage.get_string("tuning") {
+            if let Ok(tuning) = serde_json::from_str::<TuningSettings>(tuning.as_str()) {
+                self.tuning = tuning;
+                self.tuning_changed();
+            }
+        }
+
+        if let Some(modules) = storage.get_string("modules") {
+            if let Ok(modules) = serde_json::from_str(modules.as_str()) {
+                self.modules = modules;
+            }
+        }
     }
 
     fn save(&mut self, storage: &mut dyn epi::Storage) {
         let nodes_ron = serde_json::to_string(&self.nodes).unwrap();
 
         storage.set_string("nodes", nodes_ron);
+        storage.set_string("modules", serde_json::to_string(&self.modules).unwrap());
+        storage.set_string(
+            "audio",
+            serde_json::to_string(&self.audio_settings).unwrap(),
+        );
+        storage.set_string("transport", serde_json::to_string(&self.transport).unwrap());
+        storage.set_string("tuning", serde_json::to_string(&self.tuning).unwrap());
 
         storage.flush();
     }
@@ -83,6 +331,9 @@ impl epi::App for App {
     }
 
     fn update(&mut self, ctx: &CtxRef, _frame: &mut epi::Frame) {
+        self.driver.collect_garbage();
+        self.handle_driver_events();
+
         SidePanel::left("side_panel", 200.0).show(ctx, |ui| {
             ui.heading("Rust synth");
 
@@ -97,24 +348,152 @@ impl epi::App for App {
 
                         if self.visualiser_freq != prev {
                             self.driver.set_freq(self.visualiser_freq);
-                            self.nodes.calculate_segments(self.visualiser_freq);
+                            self.refresh_previews();
+                        }
+                    });
+
+                    ui.horizontal(|ui| {
+                        ui.label("Preview periods: ");
+
+                        let settings = &mut self.patch().preview_settings;
+                        let prev = settings.periods;
+
+                        ui.add(DragValue::f64(&mut settings.periods).speed(0.1));
+                        settings.periods = settings.periods.max(0.1).min(1000.0);
+
+                        if settings.periods != prev {
+                            let freq = self.visualiser_freq;
+                            self.patch().calculate_segments(freq);
                         }
                     });
+
+                    ui.checkbox(&mut self.show_scope, "Scope");
+
+                    ui.collapsing("Audio", |ui| {
+                        self.audio_ui(ui);
+                    });
+
+                    let mut tuning_changed = false;
+
+                    ui.collapsing("Tuning", |ui| {
+                        tuning_changed = self.tuning.ui(ui);
+                    });
+
+                    if tuning_changed {
+                        self.tuning_changed();
+                    }
+                });
+            });
+
+            ui.group(|ui| {
+                ui.vertical(|ui| {
+                    ui.heading("Transport");
+
+                    let position = self.driver.transport.get();
+
+                    for command in self.transport.ui(ui, position) {
+                        self.driver.transport(command);
+                    }
+
+                    // keep the position display moving
+                    if self.transport.playing {
+                        ui.ctx().request_repaint();
+                    }
+                });
+            });
+
+            ui.group(|ui| {
+                ui.vertical(|ui| {
+                    ui.heading("Patch");
+
+                    self.patch_ui(ui);
+                });
+            });
+
+            ui.group(|ui| {
+                ui.vertical(|ui| {
+                    ui.heading("Canvas");
+
+                    let patch = self.patch();
+
+                    ui.horizontal(|ui| {
+                        if ui.button("Frame all").clicked() {
+                            patch.frame_all();
+                        }
+
+                        if ui.button("Auto layout").clicked() {
+                            patch.auto_layout();
+                            patch.frame_all();
+                        }
+                    });
+
+                    ui.checkbox(&mut patch.view.snap, "Snap to grid");
+                    ui.checkbox(&mut patch.view.animate, "Animate wires");
+
+                    ui.label(format!("Zoom: {:.0}%", patch.view.zoom * 100.0));
+                });
+            });
+
+            ui.group(|ui| {
+                ui.vertical(|ui| {
+                    ui.heading("Add node");
+
+                    ScrollArea::auto_sized().show(ui, |ui| {
+                        self.add_ui(ui);
+                    });
                 });
             });
         });
 
+        self.scope.update(&self.driver.scope);
+
+        if self.show_scope {
+            let scope = &mut self.scope;
+            let nodes = &mut self.nodes;
+            let buffer = &self.driver.scope;
+            let mut probe_changed = false;
+
+            Window::new("Scope")
+                .open(&mut self.show_scope)
+                .show(ctx, |ui| {
+                    probe_changed = scope.ui(ui, nodes, buffer);
+                });
+
+            if probe_changed {
+                self.driver.set_probe(self.scope.probe.clone());
+            }
+
+            ctx.request_repaint();
+        }
+
         let frame = Frame {
             fill: ctx.style().visuals.extreme_bg_color,
             ..Frame::none()
         };
 
         CentralPanel::default().frame(frame).show(ctx, |ui| {
-            let mutated = self.nodes.ui(ui, self.visualiser_freq);
+            let freq = self.visualiser_freq;
+            let patch = self.patch();
+
+            let mutated = patch.ui(ui);
+            let entered = patch.entered.take();
+            let param_changes = std::mem::take(&mut patch.param_changes);
+
+            if patch.update_preview() {
+                ui.ctx().request_repaint();
+            }
+
             self.visualiser_freq = self.visualiser_freq.max(1.0);
 
             if mutated {
-                self.driver.set_nodes(self.nodes.clone());
+                self.graph_changed();
+            } else if !param_changes.is_empty() {
+                self.params_changed(param_changes);
+            }
+
+            if let Some(id) = entered {
+                self.path.push(id);
+                self.patch().calculate_segments(freq);
             }
         });
     }
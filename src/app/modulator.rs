@@ -30,11 +30,11 @@ impl Node for LowPassFilter {
     }
 
     fn input_slot_types(&self) -> &[(&'static str, SlotType)] {
-        &[("freq", SlotType::Float), ("in", SlotType::Float)]
+        &[("freq", SlotType::Pitch), ("in", SlotType::Audio)]
     }
 
     fn output_slot_types(&self) -> &[(&'static str, SlotType)] {
-        &[("freq_out", SlotType::Float), ("out", SlotType::Float)]
+        &[("freq_out", SlotType::Pitch), ("out", SlotType::Audio)]
     }
 
     fn save_last_output(&self) -> &Option<&str> {
@@ -46,7 +46,7 @@ impl Node for LowPassFilter {
     }
 
     fn run(
-        &self,
+        &mut self,
         ctx: &NodeCtx,
         input: HashMap<String, SlotValue>,
     ) -> Vec<(&'static str, SlotValue)> {
@@ -71,4 +71,14 @@ impl Node for LowPassFilter {
 
         changed
     }
+
+    fn params(&self) -> Vec<f64> {
+        vec![self.cutoff]
+    }
+
+    fn set_param(&mut self, index: usize, value: f64) {
+        if index == 0 {
+            self.cutoff = value;
+        }
+    }
 }
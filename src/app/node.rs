@@ -1,27 +1,100 @@
+use crate::canvas::*;
 use crate::freq_nodes::*;
+use crate::preview::*;
+use crate::transport::Transport;
+use crate::tuning::Tuning;
 use eframe::egui::{plot::*, *};
 use serde::{Deserialize, Serialize};
 use serde_traitobject as s;
 use std::collections::HashMap;
+use std::sync::Arc;
 
 #[derive(Clone)]
-pub struct NodeCtx {
+pub struct NodeCtx<'a> {
     pub freq: f64,
     pub time: f64,
     pub sample_length: f64,
     pub last_sample: f64,
+    /// Frame read from the audio input.
+    pub audio_in: (f64, f64),
+    pub transport: Transport,
+    /// Converts note numbers to frequencies.
+    pub tuning: &'a Tuning,
 }
 
 #[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
 pub enum SlotType {
-    Float,
+    /// Audio rate signal, nominally in -1..1.
+    Audio,
+    /// Frequency in Hz.
+    Pitch,
+    /// Slowly changing modulation value.
+    Control,
+    /// 1 while held, 0 otherwise.
+    Gate,
+    /// Rising edges start events, the length of the pulse doesn't matter.
+    Trigger,
+    /// Left and right audio signals.
+    Stereo,
 }
 
-impl SlotType {}
+impl SlotType {
+    pub const ALL: [SlotType; 6] = [
+        SlotType::Audio,
+        SlotType::Pitch,
+        SlotType::Control,
+        SlotType::Gate,
+        SlotType::Trigger,
+        SlotType::Stereo,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            SlotType::Audio => "Audio",
+            SlotType::Pitch => "Pitch",
+            SlotType::Control => "Control",
+            SlotType::Gate => "Gate",
+            SlotType::Trigger => "Trigger",
+            SlotType::Stereo => "Stereo",
+        }
+    }
+
+    /// Colour used for slots and wires of this type.
+    pub fn color(&self) -> Color32 {
+        match self {
+            SlotType::Audio => Color32::from_rgb(90, 170, 230),
+            SlotType::Pitch => Color32::from_rgb(230, 170, 60),
+            SlotType::Control => Color32::from_rgb(120, 200, 120),
+            SlotType::Gate => Color32::from_rgb(220, 90, 90),
+            SlotType::Trigger => Color32::from_rgb(230, 120, 200),
+            SlotType::Stereo => Color32::from_rgb(150, 120, 230),
+        }
+    }
+
+    /// Whether an output of this type can be connected to an input of type `to`.
+    pub fn converts_to(self, to: SlotType) -> bool {
+        use SlotType::*;
+
+        match (self, to) {
+            (from, to) if from == to => true,
+            (Audio, Control) | (Control, Audio) => true,
+            // oscillators wired into frequencies, for vibrato and FM
+            (Audio, Pitch) | (Pitch, Audio) => true,
+            (Pitch, Control) | (Control, Pitch) => true,
+            (Gate, Trigger) | (Trigger, Gate) => true,
+            (Gate, Control) | (Trigger, Control) => true,
+            (Gate, Audio) | (Trigger, Audio) => true,
+            (Control, Gate) | (Control, Trigger) => true,
+            (Audio, Stereo) | (Control, Stereo) | (Stereo, Audio) => true,
+            _ => false,
+        }
+    }
+}
 
 #[derive(Clone, Copy, Debug, Serialize, Deserialize)]
 pub enum SlotValue {
     Float(f64),
+    Stereo(f64, f64),
     None,
 }
 
@@ -29,9 +102,47 @@ impl SlotValue {
     pub fn unwrap_f64(self, default: f64) -> f64 {
         match self {
             SlotValue::Float(f) => f,
+            SlotValue::Stereo(l, r) => (l + r) * 0.5,
             SlotValue::None => default,
         }
     }
+
+    pub fn unwrap_stereo(self, default: f64) -> (f64, f64) {
+        match self {
+            SlotValue::Float(f) => (f, f),
+            SlotValue::Stereo(l, r) => (l, r),
+            SlotValue::None => (default, default),
+        }
+    }
+
+    /// Replaces NaN and infinite values with 0, so one node can't break every
+    /// node after it.
+    pub fn sanitize(self) -> SlotValue {
+        let finite = |value: f64| if value.is_finite() { value } else { 0.0 };
+
+        match self {
+            SlotValue::Float(f) => SlotValue::Float(finite(f)),
+            SlotValue::Stereo(l, r) => SlotValue::Stereo(finite(l), finite(r)),
+            SlotValue::None => SlotValue::None,
+        }
+    }
+
+    /// Converts a value sent from a slot of type `from` to one of type `to`,
+    /// see [`SlotType::converts_to`].
+    pub fn convert(self, from: SlotType, to: SlotType) -> SlotValue {
+        match (self, to) {
+            (SlotValue::None, _) => SlotValue::None,
+            (SlotValue::Stereo(..), SlotType::Stereo) => self,
+            (SlotValue::Stereo(l, r), _) => SlotValue::Float((l + r) * 0.5),
+            (SlotValue::Float(f), SlotType::Stereo) => SlotValue::Stereo(f, f),
+            (SlotValue::Float(f), SlotType::Gate) | (SlotValue::Float(f), SlotType::Trigger)
+                if from == SlotType::Control =>
+            {
+                SlotValue::Float(if f > 0.5 { 1.0 } else { 0.0 })
+            }
+            _ => self,
+        }
+    }
 }
 
 #[derive(Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
@@ -65,12 +176,51 @@ pub trait Node: 'static + Send + Sync + NodeClone + s::Serialize + s::Deserializ
     }
 
     fn run(
-        &self,
+        &mut self,
         ctx: &NodeCtx,
         input: HashMap<String, SlotValue>,
     ) -> Vec<(&'static str, SlotValue)>;
 
+    /// Draws the node's controls. Returns true if the whole graph must be
+    /// sent again, which is only needed for changes `params` doesn't cover,
+    /// like ones changing the slots or switching modes.
     fn ui(&mut self, ui: &mut Ui) -> bool;
+
+    /// Values of the node's continuous controls. Changes to these are sent to
+    /// the running graph one at a time instead of resending the whole graph,
+    /// so `ui` doesn't report them, and they are ramped to their new values.
+    fn params(&self) -> Vec<f64> {
+        Vec::new()
+    }
+
+    /// Sets the parameter at `index` of `params`, called on the audio thread.
+    fn set_param(&mut self, _index: usize, _value: f64) {}
+
+    /// Loads data that isn't serialized, such as audio files. Called on the
+    /// UI thread when a patch is loaded or the node is added.
+    fn load_resources(&mut self) {}
+
+    /// The nested graph of container nodes, which can be entered for editing.
+    fn subpatch(&mut self) -> Option<&mut NodeManager> {
+        None
+    }
+}
+
+/// A change to one of the `params` of a node.
+#[derive(Clone, Copy)]
+pub struct ParamChange {
+    pub id: NodeId,
+    pub index: usize,
+    pub from: f64,
+    pub to: f64,
+}
+
+/// An output shown by the scope, of a node in the subpatch at `path`.
+#[derive(Clone, PartialEq)]
+pub struct Probe {
+    pub path: Vec<NodeId>,
+    pub id: NodeId,
+    pub slot: String,
 }
 
 #[derive(Clone, Serialize, Deserialize)]
@@ -82,7 +232,7 @@ impl Node for OutputNode {
     }
 
     fn input_slot_types(&self) -> &[(&'static str, SlotType)] {
-        &[("out", SlotType::Float)]
+        &[("out", SlotType::Stereo)]
     }
 
     fn output_slot_types(&self) -> &[(&'static str, SlotType)] {
@@ -90,7 +240,7 @@ impl Node for OutputNode {
     }
 
     fn run(
-        &self,
+        &mut self,
         _ctx: &NodeCtx,
         input: HashMap<String, SlotValue>,
     ) -> Vec<(&'static str, SlotValue)> {
@@ -107,7 +257,13 @@ pub struct NodeContainer {
     #[serde(with = "serde_traitobject")]
     pub inner: Box<dyn Node>,
     pub connections: HashMap<String, (NodeId, String)>,
+    /// Slot types at both ends of each connection, resolved when connecting
+    /// or loading so running the graph doesn't look them up.
+    #[serde(skip)]
+    pub conversions: HashMap<String, (SlotType, SlotType)>,
     pub last_sample: Option<f64>,
+    #[serde(default)]
+    pub position: Pos2,
 }
 
 impl Clone for NodeContainer {
@@ -115,7 +271,9 @@ impl Clone for NodeContainer {
         Self {
             inner: self.inner.box_clone(),
             connections: self.connections.clone(),
+            conversions: self.conversions.clone(),
             last_sample: self.last_sample.clone(),
+            position: self.position,
         }
     }
 }
@@ -125,7 +283,18 @@ impl NodeContainer {
         Self {
             inner: Box::new(node),
             connections: HashMap::new(),
+            conversions: HashMap::new(),
             last_sample: None,
+            position: Pos2::ZERO,
+        }
+    }
+
+    /// Loads the resources of the node and of every node in its subpatch.
+    pub fn load_resources(&mut self) {
+        self.inner.load_resources();
+
+        if let Some(nodes) = self.inner.subpatch() {
+            nodes.load_resources();
         }
     }
 }
@@ -135,7 +304,9 @@ impl From<Box<dyn Node>> for NodeContainer {
         Self {
             inner: node.into(),
             connections: HashMap::new(),
+            conversions: HashMap::new(),
             last_sample: None,
+            position: Pos2::ZERO,
         }
     }
 }
@@ -148,6 +319,39 @@ pub struct NodeManager {
     pub input_node: NodeId,
     pub output_node: NodeId,
     pub segments: HashMap<NodeId, Vec<f64>>,
+    /// Periods of the preview frequency between the points of the segments.
+    #[serde(default = "default_segment_spacing")]
+    pub segment_spacing: f64,
+    #[serde(default)]
+    pub preview_settings: PreviewSettings,
+    #[serde(skip)]
+    pub preview: PreviewSlot,
+    #[serde(default)]
+    pub view: CanvasView,
+    /// Set by the editor when the user asks to enter a node's subpatch.
+    #[serde(skip)]
+    pub entered: Option<NodeId>,
+    /// Parameters changed in the editor, to be sent to the driver.
+    #[serde(skip)]
+    pub param_changes: Vec<ParamChange>,
+    /// The tuning the previews are calculated with.
+    #[serde(skip)]
+    pub tuning: Arc<Tuning>,
+    /// Node run even if nothing reads from it, the probed one or the group
+    /// it is in.
+    #[serde(skip)]
+    pub probe: Option<NodeId>,
+    /// The probed output of `probe`, if it is in this patch.
+    #[serde(skip)]
+    pub probe_slot: Option<&'static str>,
+    /// The value of the probed output at the last run, `None` if it had no
+    /// signal.
+    #[serde(skip)]
+    pub probed: Option<f64>,
+}
+
+fn default_segment_spacing() -> f64 {
+    PreviewSettings::default().periods / PREVIEW_POINTS as f64
 }
 
 impl From<Vec<Box<dyn Node>>> for NodeManager {
@@ -176,9 +380,38 @@ impl NodeManager {
             input_node: NodeId(0),
             output_node: NodeId(1),
             segments: HashMap::new(),
+            segment_spacing: default_segment_spacing(),
+            preview_settings: PreviewSettings::default(),
+            preview: PreviewSlot::default(),
+            view: CanvasView::default(),
+            entered: None,
+            param_changes: Vec::new(),
+            tuning: Arc::default(),
+            probe: None,
+            probe_slot: None,
+            probed: None,
         }
     }
 
+    pub fn load_resources(&mut self) {
+        for node in self.nodes.values_mut() {
+            node.load_resources();
+        }
+
+        self.remove_invalid_connections();
+    }
+
+    /// Sets the tuning of this patch and every subpatch in it.
+    pub fn set_tuning(&mut self, tuning: Arc<Tuning>) {
+        for node in self.nodes.values_mut() {
+            if let Some(nodes) = node.inner.subpatch() {
+                nodes.set_tuning(tuning.clone());
+            }
+        }
+
+        self.tuning = tuning;
+    }
+
     pub fn add(&mut self, node: NodeContainer) -> NodeId {
         let id = self.next_id;
         self.nodes.insert(id, node);
@@ -187,63 +420,267 @@ impl NodeManager {
         id
     }
 
-    pub fn run(&mut self, ctx: &NodeCtx) -> f64 {
+    /// Connects the `output` slot of `from` to the `input` slot of `to`,
+    /// replacing whatever was previously connected to that input.
+    ///
+    /// Returns `false` if the connection would create a cycle.
+    pub fn connect(&mut self, from: NodeId, output: &str, to: NodeId, input: &str) -> bool {
+        if from == to || self.depends_on(from, to) {
+            return false;
+        }
+
+        let types = match (self.output_type(from, output), self.input_type(to, input)) {
+            (Some(from), Some(to)) if from.converts_to(to) => (from, to),
+            _ => return false,
+        };
+
+        if let Some(node) = self.nodes.get_mut(&to) {
+            node.connections
+                .insert(input.to_string(), (from, output.to_string()));
+            node.conversions.insert(input.to_string(), types);
+
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn disconnect(&mut self, id: NodeId, input: &str) -> Option<(NodeId, String)> {
+        let node = self.nodes.get_mut(&id)?;
+        node.conversions.remove(input);
+
+        node.connections.remove(input)
+    }
+
+    pub fn input_type(&self, id: NodeId, input: &str) -> Option<SlotType> {
+        let slots = self.nodes.get(&id)?.inner.input_slot_types();
+
+        slots
+            .iter()
+            .find(|(name, _)| *name == input)
+            .map(|(_, ty)| *ty)
+    }
+
+    pub fn output_type(&self, id: NodeId, output: &str) -> Option<SlotType> {
+        let slots = self.nodes.get(&id)?.inner.output_slot_types();
+
+        slots
+            .iter()
+            .find(|(name, _)| *name == output)
+            .map(|(_, ty)| *ty)
+    }
+
+    /// Removes connections to slots that no longer exist or whose types no
+    /// longer match, for instance after the ports of a group changed, and
+    /// resolves the types of the others. Returns true if any were removed.
+    pub fn remove_invalid_connections(&mut self) -> bool {
+        let mut invalid = Vec::new();
+        let mut resolved = Vec::new();
+
+        for (id, node) in &self.nodes {
+            for (input, (o_id, output)) in &node.connections {
+                match (self.output_type(*o_id, output), self.input_type(*id, input)) {
+                    (Some(from), Some(to)) if from.converts_to(to) => {
+                        resolved.push((*id, input.clone(), (from, to)))
+                    }
+                    _ => invalid.push((*id, input.clone())),
+                }
+            }
+        }
+
+        for (id, input, types) in resolved {
+            if let Some(node) = self.nodes.get_mut(&id) {
+                node.conversions.insert(input, types);
+            }
+        }
+
+        let removed = !invalid.is_empty();
+
+        for (id, input) in invalid {
+            self.disconnect(id, &input);
+        }
+
+        removed
+    }
+
+    /// Returns true if `id` reads from `dependency`, directly or through other nodes.
+    pub fn depends_on(&self, id: NodeId, dependency: NodeId) -> bool {
+        let mut stack = vec![id];
+        let mut visited = Vec::new();
+
+        while let Some(id) = stack.pop() {
+            if id == dependency {
+                return true;
+            }
+
+            if visited.contains(&id) {
+                continue;
+            }
+
+            visited.push(id);
+
+            if let Some(node) = self.nodes.get(&id) {
+                stack.extend(node.connections.values().map(|(id, _)| *id));
+            }
+        }
+
+        false
+    }
+
+    /// Number of inputs the `output` slot of `id` is connected to.
+    pub fn fan_out(&self, id: NodeId, output: &str) -> usize {
+        self.nodes
+            .values()
+            .flat_map(|node| node.connections.values())
+            .filter(|(o_id, o_slot)| *o_id == id && o_slot == output)
+            .count()
+    }
+
+    pub fn run(&mut self, ctx: &NodeCtx) -> (f64, f64) {
         let mut outputs: HashMap<(NodeId, &'static str), SlotValue> = HashMap::new();
 
         let inputs = self.gen_inputs(ctx, &self.output_node.clone(), &mut outputs);
         self.run_node(ctx, &self.output_node.clone(), inputs, &mut outputs);
+        self.run_probe(ctx, &mut outputs);
 
         outputs[&(self.output_node, "output node")]
             .clone()
-            .unwrap_f64(0.0)
+            .unwrap_stereo(0.0)
+    }
+
+    /// Probes `probe` and stops probing anything else, in this patch and in
+    /// every subpatch.
+    pub fn set_probe(&mut self, probe: Option<&Probe>) {
+        self.clear_probe();
+
+        let probe = match probe {
+            Some(probe) => probe,
+            None => return,
+        };
+
+        let mut nodes = self;
+
+        // each group on the way is run, so the probe inside it is
+        for id in &probe.path {
+            nodes.probe = Some(*id);
+            nodes = match nodes
+                .nodes
+                .get_mut(id)
+                .and_then(|node| node.inner.subpatch())
+            {
+                Some(nodes) => nodes,
+                None => return,
+            };
+        }
+
+        nodes.probe = Some(probe.id);
+        nodes.probe_slot = nodes.nodes.get(&probe.id).and_then(|node| {
+            node.inner
+                .output_slot_types()
+                .iter()
+                .find(|(name, _)| *name == probe.slot)
+                .map(|(name, _)| *name)
+        });
+    }
+
+    fn clear_probe(&mut self) {
+        self.probe = None;
+        self.probe_slot = None;
+        self.probed = None;
+
+        for node in self.nodes.values_mut() {
+            if let Some(nodes) = node.inner.subpatch() {
+                nodes.clear_probe();
+            }
+        }
     }
 
-    const NUM_SAMPLES: usize = 100;
+    /// Takes the value of `probe` at the last run, `None` if it had no signal
+    /// or its patch wasn't run since.
+    pub fn probed(&mut self, probe: &Probe) -> Option<f64> {
+        self.subpatch_at(&probe.path)?.probed.take()
+    }
 
-    pub fn calculate_segments(&mut self, freq: f64) {
-        self.segments.clear();
+    /// Runs the probed node if nothing read from it, and keeps its output.
+    fn run_probe(
+        &mut self,
+        ctx: &NodeCtx,
+        outputs: &mut HashMap<(NodeId, &'static str), SlotValue>,
+    ) {
+        let id = match self.probe {
+            Some(id) if self.nodes.contains_key(&id) => id,
+            _ => return,
+        };
+
+        if !outputs.keys().any(|(o_id, _)| *o_id == id) {
+            let inputs = self.gen_inputs(ctx, &id, outputs);
+            self.run_node(ctx, &id, inputs, outputs);
+        }
 
-        for id in self.nodes.keys().cloned().collect::<Vec<_>>() {
-            let node = self.nodes.get_mut(&id).unwrap();
+        self.probed = match self.probe_slot.and_then(|slot| outputs.get(&(id, slot))) {
+            Some(SlotValue::None) | None => None,
+            Some(value) => Some(value.unwrap_f64(0.0)),
+        };
+    }
 
-            node.inner.setup();
-            node.last_sample = None;
+    /// Runs the graph with the outputs of the input node taken from `inputs`,
+    /// returning the outputs of every node that was evaluated.
+    pub fn run_with_inputs(
+        &mut self,
+        ctx: &NodeCtx,
+        inputs: &HashMap<String, SlotValue>,
+    ) -> HashMap<(NodeId, &'static str), SlotValue> {
+        let mut outputs = HashMap::new();
 
-            for i in 0..Self::NUM_SAMPLES {
-                let mut output = HashMap::new();
+        let input_node = self.input_node;
 
-                let sample_length = 2.0 / Self::NUM_SAMPLES as f64 / freq;
+        for (name, _ty) in self.nodes[&input_node].inner.output_slot_types() {
+            if let Some(value) = inputs.get(*name) {
+                outputs.insert((input_node, *name), *value);
+            }
+        }
 
-                let mut ctx = NodeCtx {
-                    freq,
-                    sample_length,
-                    time: i as f64 * sample_length,
-                    last_sample: self.nodes[&id].last_sample.unwrap_or(0.0),
-                };
+        let output_node = self.output_node;
 
-                let mut inputs = self.gen_inputs(&ctx, &id.clone(), &mut output);
+        let node_inputs = self.gen_inputs(ctx, &output_node, &mut outputs);
+        self.run_node(ctx, &output_node, node_inputs, &mut outputs);
+        self.run_probe(ctx, &mut outputs);
 
-                if let Some(freq) = inputs.get("freq") {
-                    let freq = freq.unwrap_f64(ctx.freq);
-                    ctx.sample_length = 2.0 / Self::NUM_SAMPLES as f64 / freq;
-                    ctx.time = i as f64 * ctx.sample_length;
+        outputs
+    }
 
-                    output = HashMap::new();
+    /// Peak level of the preview of `id`, if `slot` is the output being previewed.
+    pub fn activity(&self, id: NodeId, slot: &str) -> f32 {
+        let display = self
+            .nodes
+            .get(&id)
+            .and_then(|node| *node.inner.display_out());
 
-                    inputs = self.gen_inputs(&ctx, &id.clone(), &mut output);
-                }
+        match (display, self.segments.get(&id)) {
+            (Some(display), Some(segment)) if display == slot => {
+                segment.iter().fold(0.0, |peak, s| s.abs().max(peak)) as f32
+            }
+            _ => 0.0,
+        }
+    }
 
-                self.run_node(&ctx, &id.clone(), inputs, &mut output);
+    /// Runs every node once, including those not connected to the output.
+    pub fn run_all(&mut self, ctx: &NodeCtx) -> HashMap<(NodeId, &'static str), SlotValue> {
+        let mut outputs = HashMap::new();
 
-                if let Some(name) = self.nodes[&id].inner.display_out() {
-                    if let Some(value) = output.get(&(id, name)) {
-                        if let SlotValue::Float(s) = value {
-                            self.segments.entry(id).or_insert(Vec::new()).push(*s);
-                        }
-                    }
-                }
+        let mut ids = self.nodes.keys().cloned().collect::<Vec<_>>();
+        ids.sort_by_key(|id| id.0);
+
+        for id in ids {
+            if outputs.keys().any(|(o_id, _)| *o_id == id) {
+                continue;
             }
+
+            let inputs = self.gen_inputs(ctx, &id, &mut outputs);
+            self.run_node(ctx, &id, inputs, &mut outputs);
         }
+
+        outputs
     }
 
     pub fn gen_inputs(
@@ -262,8 +699,17 @@ impl NodeManager {
                 self.run_node(ctx, &node, inputs, outputs);
             }
 
-            if let Some(output) = outputs.get(&(node, &output)) {
-                inputs.insert(input.to_string(), output.clone());
+            // resolved when connecting or loading, looked up for graphs
+            // that were changed without either
+            let types = match self.nodes[id].conversions.get(&input) {
+                Some(types) => Some(*types),
+                None => self
+                    .output_type(node, &output)
+                    .zip(self.input_type(*id, &input)),
+            };
+
+            if let (Some(value), Some((from, to))) = (outputs.get(&(node, &output)), types) {
+                inputs.insert(input.to_string(), value.convert(from, to));
             }
         }
 
@@ -293,7 +739,7 @@ impl NodeManager {
         let output = node.inner.run(&ctx, inputs);
 
         for (name, value) in output {
-            outputs.insert((*id, name), value);
+            outputs.insert((*id, name), value.sanitize());
         }
 
         if let Some(slot) = self.nodes[&id].inner.save_last_output() {
@@ -302,114 +748,310 @@ impl NodeManager {
         }
     }
 
-    pub fn ui(&mut self, ui: &mut Ui, freq: f64) -> bool {
+    /// Draws the editor, returns true if the graph changed. The previews are
+    /// refreshed by the caller, along with those of the patches around it.
+    pub fn ui(&mut self, ui: &mut Ui) -> bool {
+        let canvas = ui.interact(
+            ui.max_rect(),
+            ui.id().with("node_canvas"),
+            Sense::click_and_drag(),
+        );
+
+        self.view.interact(ui, &canvas);
+        self.view.draw_grid(ui, canvas.rect);
+
+        let origin = canvas.rect.min;
+        let collapsed = self.view.collapsed();
+
         let selected_slot = &mut self.selected_slot;
         let segments = &self.segments;
+        let spacing = self.segment_spacing;
+        let periods = self.preview_settings.periods;
+        let view = &mut self.view;
+        let param_changes = &mut self.param_changes;
 
         let mut input_slot_positions = HashMap::new();
         let mut output_slot_positions = HashMap::new();
+        let mut new_connections = Vec::new();
 
         let mut mutated = false;
+        let mut nodes_changed = false;
+        let mut entered = None;
+        let mut wrapped = None;
+        let (input_node, output_node) = (self.input_node, self.output_node);
 
         for (id, node) in &mut self.nodes {
-            Area::new(id.clone()).show(&ui.ctx(), |ui| {
-                ui.group(|ui| {
-                    ui.vertical(|ui| {
-                        ui.heading(node.inner.name());
-
-                        ui.horizontal(|ui| {
-                            ui.vertical(|ui| {
-                                for (slot, ty) in node.inner.input_slot_types() {
-                                    let (pos, connect) = input(slot, *id, *ty, selected_slot, ui);
-
-                                    if connect {
-                                        if let Some((s_slot, id, is_input, s_ty)) = selected_slot {
-                                            if !*is_input && ty == s_ty {
-                                                node.connections.insert(
-                                                    slot.to_string(),
-                                                    (*id, s_slot.to_string()),
-                                                );
+            let pos = view.to_screen(origin, node.position);
+
+            let response = Area::new(id.clone())
+                .current_pos(pos)
+                .show(&ui.ctx(), |ui| {
+                    ui.group(|ui| {
+                        ui.vertical(|ui| {
+                            ui.horizontal(|ui| {
+                                ui.heading(node.inner.name());
+
+                                if node.inner.subpatch().is_some() && ui.button("Enter").clicked() {
+                                    entered = Some(*id);
+                                }
+
+                                // wrapped nodes are moved into a group, so
+                                // they can be run faster than the patch
+                                if node.inner.subpatch().is_none()
+                                    && *id != input_node
+                                    && *id != output_node
+                                    && ui.button("Oversample").clicked()
+                                {
+                                    wrapped = Some(*id);
+                                }
+                            });
+
+                            ui.horizontal(|ui| {
+                                ui.vertical(|ui| {
+                                    for (slot, ty) in node.inner.input_slot_types() {
+                                        let (pos, connect, drag_started) =
+                                            input(slot, *id, *ty, selected_slot, ui);
+
+                                        // dragging a connected input picks the wire up by its
+                                        // output end, so it can be dropped elsewhere or discarded
+                                        if drag_started {
+                                            if let Some((o_id, o_slot)) =
+                                                node.connections.remove(*slot)
+                                            {
+                                                *selected_slot = Some((o_slot, o_id, false, *ty));
 
                                                 mutated = true;
                                             }
                                         }
+
+                                        if connect {
+                                            if let Some((s_slot, s_id, is_input, _)) = selected_slot
+                                            {
+                                                if !*is_input {
+                                                    new_connections.push((
+                                                        *s_id,
+                                                        s_slot.clone(),
+                                                        *id,
+                                                        slot.to_string(),
+                                                    ));
+                                                }
+                                            }
+                                        }
+
+                                        input_slot_positions.insert((*slot, *id), (pos, *ty));
                                     }
+                                });
 
-                                    input_slot_positions.insert((*slot, *id), pos);
+                                if !collapsed {
+                                    let params = node.inner.params();
+                                    let changed = node.inner.ui(ui);
+                                    nodes_changed = changed || nodes_changed;
+
+                                    let changed_params = params
+                                        .into_iter()
+                                        .zip(node.inner.params())
+                                        .enumerate()
+                                        .filter(|(_, (from, to))| from != to)
+                                        .map(|(index, (from, to))| ParamChange {
+                                            id: *id,
+                                            index,
+                                            from,
+                                            to,
+                                        })
+                                        .collect::<Vec<_>>();
+
+                                    // only resend the graph for changes that
+                                    // aren't covered by the parameters
+                                    if changed_params.is_empty() {
+                                        mutated = changed || mutated;
+                                    } else {
+                                        param_changes.extend(changed_params);
+                                    }
                                 }
-                            });
 
-                            mutated = node.inner.ui(ui) || mutated;
+                                if let (Some(segment), false) = (segments.get(id), collapsed) {
+                                    ui.vertical(|ui| {
+                                        // the x axis is in periods of the preview frequency
+                                        let curve = Curve::from_values_iter(
+                                            segment
+                                                .iter()
+                                                .enumerate()
+                                                .map(|(i, s)| Value::new(i as f64 * spacing, *s)),
+                                        );
+
+                                        let color = Color32::from_gray(130).additive();
+
+                                        let stroke = Stroke { width: 1.0, color };
+
+                                        let mut plot = Plot::default()
+                                            .curve(curve)
+                                            .symmetrical_y_bounds(true)
+                                            .view_aspect(1.0);
+
+                                        if periods <= 16.0 {
+                                            for period in 1..=periods.floor() as usize {
+                                                plot =
+                                                    plot.vline(VLine::new(period as f64, stroke));
+                                            }
+                                        }
 
-                            if let Some(segment) = segments.get(id) {
-                                ui.vertical(|ui| {
-                                    let curve = Curve::from_values_iter(
-                                        segment.iter().enumerate().map(|(i, s)| {
-                                            Value::new(
-                                                i as f64 * (1.0 / Self::NUM_SAMPLES as f64) * 2.0,
-                                                *s,
-                                            )
-                                        }),
-                                    );
-
-                                    let color = Color32::from_gray(130).additive();
-
-                                    let stroke = Stroke { width: 1.0, color };
-
-                                    let plot = Plot::default()
-                                        .curve(curve)
-                                        .symmetrical_y_bounds(true)
-                                        .view_aspect(1.0)
-                                        .vline(VLine::new(2.0, stroke));
-
-                                    ui.add(plot);
-                                });
-                            }
+                                        ui.add(plot);
+                                    });
+                                }
 
-                            ui.vertical(|ui| {
-                                for (name, ty) in node.inner.output_slot_types() {
-                                    let (pos, _connect) = output(name, *id, *ty, selected_slot, ui);
+                                ui.vertical(|ui| {
+                                    for (name, ty) in node.inner.output_slot_types() {
+                                        let (pos, connect, _drag_started) =
+                                            output(name, *id, *ty, selected_slot, ui);
+
+                                        if connect {
+                                            if let Some((s_slot, s_id, is_input, _)) = selected_slot
+                                            {
+                                                if *is_input {
+                                                    new_connections.push((
+                                                        *id,
+                                                        name.to_string(),
+                                                        *s_id,
+                                                        s_slot.clone(),
+                                                    ));
+                                                }
+                                            }
+                                        }
 
-                                    output_slot_positions.insert((*name, *id), pos);
-                                }
+                                        output_slot_positions.insert((*name, *id), (pos, *ty));
+                                    }
+                                });
                             });
                         });
                     });
                 });
-            });
+
+            if response.dragged() {
+                node.position += ui.input().pointer.delta() / view.zoom;
+            }
+
+            if response.drag_released() {
+                node.position = view.snap(node.position);
+            }
+
+            view.node_sizes.insert(*id, response.rect.size());
+        }
+
+        if entered.is_some() {
+            self.entered = entered;
+        }
+
+        if let Some(id) = wrapped {
+            mutated = self.wrap_oversampled(id, 2) || mutated;
         }
 
+        for (from, output, to, input) in new_connections {
+            mutated = self.connect(from, &output, to, &input) || mutated;
+        }
+
+        let pointer = ui.input().pointer.hover_pos();
+        let time = ui.input().time;
+
+        let mut wires = Vec::new();
+
         for (id, node) in &self.nodes {
             for (i_slot, (o_id, o_slot)) in &node.connections {
-                let i_pos = input_slot_positions[&(i_slot.as_str(), *id)];
-                let o_pos = output_slot_positions[&(o_slot.as_str(), *o_id)];
+                let i_pos = input_slot_positions.get(&(i_slot.as_str(), *id));
+                let o_pos = output_slot_positions.get(&(o_slot.as_str(), *o_id));
 
-                ui.painter()
-                    .line_segment([i_pos, o_pos], ui.style().visuals.widgets.active.fg_stroke);
+                let ((i_pos, _), (o_pos, ty)) = match (i_pos, o_pos) {
+                    (Some(i_pos), Some(o_pos)) => (*i_pos, *o_pos),
+                    _ => continue,
+                };
+
+                wires.push((
+                    *id,
+                    i_slot.clone(),
+                    wire_points(o_pos, i_pos),
+                    ty,
+                    self.activity(*o_id, o_slot),
+                ));
             }
         }
 
+        // the wire closest to the pointer is highlighted and can be clicked to remove it
+        let hovered_wire = match (pointer, &self.selected_slot) {
+            (Some(pointer), None) => wires
+                .iter()
+                .enumerate()
+                .map(|(i, (_, _, points, _, _))| (i, distance_to_wire(pointer, points)))
+                .filter(|(_, distance)| *distance < WIRE_HOVER_DISTANCE)
+                .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap())
+                .map(|(i, _)| i),
+            _ => None,
+        };
+
+        for (i, (_, _, points, ty, activity)) in wires.iter().enumerate() {
+            if hovered_wire == Some(i) {
+                let highlight = Stroke::new(6.0, ui.style().visuals.selection.bg_fill);
+                draw_wire(ui.painter(), points, highlight);
+            }
+
+            draw_wire(ui.painter(), points, Stroke::new(2.0, ty.color()));
+
+            if self.view.animate {
+                draw_activity(ui.painter(), points, ty.color(), time, *activity);
+            }
+        }
+
+        if self.view.animate && !wires.is_empty() {
+            ui.ctx().request_repaint();
+        }
+
+        // fan-out is shown as the number of wires leaving an output
+        for ((name, id), (pos, _ty)) in &output_slot_positions {
+            let fan_out = self.fan_out(*id, name);
+
+            if fan_out > 1 {
+                ui.painter().text(
+                    *pos + Vec2::new(8.0, -8.0),
+                    Align2::LEFT_BOTTOM,
+                    format!("×{}", fan_out),
+                    TextStyle::Small,
+                    ui.style().visuals.text_color(),
+                );
+            }
+        }
+
+        if let (Some(i), true) = (hovered_wire, canvas.clicked()) {
+            let (id, i_slot, _, _, _) = &wires[i];
+            self.disconnect(*id, i_slot);
+
+            mutated = true;
+        }
+
         if !ui.input().pointer.button_down(PointerButton::Primary) {
             self.selected_slot = None;
         }
 
-        if let Some((name, id, input, _ty)) = &self.selected_slot {
+        if let Some((name, id, input, ty)) = &self.selected_slot {
             let slot_positions = if *input {
                 &input_slot_positions
             } else {
                 &output_slot_positions
             };
 
-            if let Some(pos) = slot_positions.get(&(name, *id)) {
+            if let Some((pos, _)) = slot_positions.get(&(name, *id)) {
                 if let Some(pointer) = ui.input().pointer.interact_pos() {
-                    ui.painter()
-                        .line_segment([*pos, pointer], ui.style().visuals.widgets.active.fg_stroke);
+                    let points = if *input {
+                        wire_points(pointer, *pos)
+                    } else {
+                        wire_points(*pos, pointer)
+                    };
+
+                    draw_wire(ui.painter(), &points, Stroke::new(2.0, ty.color()));
                 }
             }
         }
 
-        if mutated {
-            self.calculate_segments(freq);
+        // a node's slots may have changed, like the ports of a group
+        if nodes_changed {
+            mutated = self.remove_invalid_connections() || mutated;
         }
 
         mutated
@@ -422,29 +1064,13 @@ fn input(
     ty: SlotType,
     selected_slot: &mut Option<(String, NodeId, bool, SlotType)>,
     ui: &mut Ui,
-) -> (Pos2, bool) {
+) -> (Pos2, bool, bool) {
     ui.horizontal(|ui| {
-        let desired_size = ui.spacing().interact_size.y * Vec2::new(0.5, 0.5);
-
-        let (rect, response) = ui.allocate_exact_size(desired_size, Sense::click_and_drag());
-
-        if response.drag_started() {
-            *selected_slot = Some((name.to_string(), node_id, true, ty));
-        }
-
-        let visuals = ui.style().interact(&response);
-
-        let radius = rect.height() * 0.5;
-
-        ui.painter()
-            .circle(rect.center(), radius, visuals.bg_fill, visuals.fg_stroke);
+        let (pos, connect, drag_started) = slot(name, node_id, true, ty, selected_slot, ui);
 
         ui.label(name);
 
-        (
-            rect.center(),
-            response.hovered() && !ui.input().pointer.button_down(PointerButton::Primary),
-        )
+        (pos, connect, drag_started)
     })
     .inner
 }
@@ -455,29 +1081,72 @@ fn output(
     ty: SlotType,
     selected_slot: &mut Option<(String, NodeId, bool, SlotType)>,
     ui: &mut Ui,
-) -> (Pos2, bool) {
+) -> (Pos2, bool, bool) {
     ui.horizontal(|ui| {
         ui.label(name);
 
-        let desired_size = ui.spacing().interact_size.y * Vec2::new(0.5, 0.5);
+        slot(name, node_id, false, ty, selected_slot, ui)
+    })
+    .inner
+}
+
+fn slot(
+    name: &'static str,
+    node_id: NodeId,
+    is_input: bool,
+    ty: SlotType,
+    selected_slot: &mut Option<(String, NodeId, bool, SlotType)>,
+    ui: &mut Ui,
+) -> (Pos2, bool, bool) {
+    let desired_size = ui.spacing().interact_size.y * Vec2::new(0.5, 0.5);
 
-        let (rect, response) = ui.allocate_exact_size(desired_size, Sense::click_and_drag());
+    let (rect, response) = ui.allocate_exact_size(desired_size, Sense::click_and_drag());
 
-        if response.drag_started() {
-            *selected_slot = Some((name.to_string(), node_id, false, ty));
+    // slots the dragged wire could be dropped on are highlighted, and
+    // incompatible ones are marked when hovered
+    let compatible = match selected_slot {
+        Some((_, s_id, s_input, s_ty)) if *s_id != node_id && *s_input != is_input => {
+            if is_input {
+                Some(s_ty.converts_to(ty))
+            } else {
+                Some(ty.converts_to(*s_ty))
+            }
         }
+        _ => None,
+    };
 
-        let visuals = ui.style().interact(&response);
+    if response.drag_started() {
+        *selected_slot = Some((name.to_string(), node_id, is_input, ty));
+    }
 
-        let radius = rect.height() * 0.5;
+    let visuals = ui.style().interact(&response);
 
-        ui.painter()
-            .circle(rect.center(), radius, visuals.bg_fill, visuals.fg_stroke);
+    let radius = rect.height() * 0.5;
 
-        (
-            rect.center(),
-            response.hovered() && !ui.input().pointer.button_down(PointerButton::Primary),
-        )
-    })
-    .inner
+    match compatible {
+        Some(true) => {
+            ui.painter().circle_stroke(
+                rect.center(),
+                radius + 3.0,
+                Stroke::new(2.0, ui.style().visuals.selection.bg_fill),
+            );
+        }
+        Some(false) if response.hovered() => {
+            ui.painter().circle_stroke(
+                rect.center(),
+                radius + 3.0,
+                Stroke::new(2.0, Color32::from_rgb(220, 80, 80)),
+            );
+        }
+        _ => {}
+    }
+
+    ui.painter()
+        .circle(rect.center(), radius, ty.color(), visuals.fg_stroke);
+
+    (
+        rect.center(),
+        response.hovered() && !ui.input().pointer.button_down(PointerButton::Primary),
+        response.drag_started(),
+    )
 }
@@ -0,0 +1,225 @@
+use crate::knob::knob;
+use crate::node::*;
+use eframe::egui::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum NoiseColour {
+    White,
+    /// Falls by 3 dB per octave.
+    Pink,
+    /// Falls by 6 dB per octave.
+    Brown,
+}
+
+/// A SplitMix64 generator, which gives the same sequence for the same seed
+/// on every platform.
+#[derive(Clone)]
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Self(seed)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// A uniform value in -1..1.
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64 * 2.0 - 1.0
+    }
+}
+
+/// Noise in one of three colours, optionally sampled and held on the rising
+/// edges of `trigger` or at `rate` when nothing is connected to it.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct NoiseNode {
+    colour: NoiseColour,
+    /// The same seed always gives the same noise.
+    seed: u64,
+    sample_hold: bool,
+    /// Samples per second held without a trigger.
+    rate: f64,
+    /// Started from `seed` on the first sample.
+    #[serde(skip)]
+    rng: Option<Rng>,
+    /// State of the pink and brown filters.
+    #[serde(skip)]
+    filter: [f64; 7],
+    #[serde(skip)]
+    held: f64,
+    #[serde(skip)]
+    hold_phase: f64,
+    #[serde(skip)]
+    last_trigger: f64,
+}
+
+impl NoiseNode {
+    pub fn new() -> Self {
+        Self {
+            colour: NoiseColour::White,
+            seed: 1,
+            sample_hold: false,
+            rate: 10.0,
+            rng: None,
+            filter: [0.0; 7],
+            held: 0.0,
+            hold_phase: 0.0,
+            last_trigger: 0.0,
+        }
+    }
+
+    fn reset(&mut self) {
+        self.rng = None;
+        self.filter = [0.0; 7];
+        self.held = 0.0;
+        self.hold_phase = 0.0;
+        self.last_trigger = 0.0;
+    }
+
+    fn next(&mut self) -> f64 {
+        let seed = self.seed;
+        let white = self.rng.get_or_insert_with(|| Rng::new(seed)).next_f64();
+        let b = &mut self.filter;
+
+        match self.colour {
+            NoiseColour::White => white,
+            // Paul Kellet's filter, accurate to within 0.05 dB
+            NoiseColour::Pink => {
+                b[0] = 0.99886 * b[0] + white * 0.0555179;
+                b[1] = 0.99332 * b[1] + white * 0.0750759;
+                b[2] = 0.96900 * b[2] + white * 0.1538520;
+                b[3] = 0.86650 * b[3] + white * 0.3104856;
+                b[4] = 0.55000 * b[4] + white * 0.5329522;
+                b[5] = -0.7616 * b[5] - white * 0.0168980;
+
+                let pink = b[0] + b[1] + b[2] + b[3] + b[4] + b[5] + b[6] + white * 0.5362;
+                b[6] = white * 0.115926;
+
+                pink * 0.11
+            }
+            // leaky integration keeps it from drifting off
+            NoiseColour::Brown => {
+                b[0] = (b[0] + 0.02 * white) / 1.02;
+
+                b[0] * 3.5
+            }
+        }
+    }
+}
+
+impl Node for NoiseNode {
+    fn name(&self) -> &str {
+        "Noise"
+    }
+
+    fn input_slot_types(&self) -> &[(&'static str, SlotType)] {
+        &[("trigger", SlotType::Trigger)]
+    }
+
+    fn output_slot_types(&self) -> &[(&'static str, SlotType)] {
+        &[("out", SlotType::Audio)]
+    }
+
+    fn display_out(&self) -> &Option<&str> {
+        &Some("out")
+    }
+
+    fn setup(&mut self) {
+        self.reset();
+    }
+
+    fn run(
+        &mut self,
+        ctx: &NodeCtx,
+        input: HashMap<String, SlotValue>,
+    ) -> Vec<(&'static str, SlotValue)> {
+        let noise = self.next();
+
+        if !self.sample_hold {
+            return vec![("out", SlotValue::Float(noise))];
+        }
+
+        let sample = match input["trigger"] {
+            SlotValue::None => {
+                self.hold_phase += self.rate * ctx.sample_length;
+
+                let sample = self.hold_phase >= 1.0;
+                self.hold_phase %= 1.0;
+
+                sample
+            }
+            trigger => {
+                let trigger = trigger.unwrap_f64(0.0);
+                let rising = trigger > 0.5 && self.last_trigger <= 0.5;
+
+                self.last_trigger = trigger;
+
+                rising
+            }
+        };
+
+        if sample {
+            self.held = noise;
+        }
+
+        vec![("out", SlotValue::Float(self.held))]
+    }
+
+    fn ui(&mut self, ui: &mut Ui) -> bool {
+        let prev_colour = self.colour;
+        let prev_seed = self.seed;
+        let prev_sample_hold = self.sample_hold;
+
+        ui.vertical(|ui| {
+            ui.horizontal(|ui| {
+                ui.radio_value(&mut self.colour, NoiseColour::White, "White");
+                ui.radio_value(&mut self.colour, NoiseColour::Pink, "Pink");
+                ui.radio_value(&mut self.colour, NoiseColour::Brown, "Brown");
+            });
+
+            ui.horizontal(|ui| {
+                let mut seed = self.seed as f64;
+
+                ui.label("Seed");
+                ui.add(DragValue::f64(&mut seed).speed(1.0));
+
+                self.seed = seed.max(0.0) as u64;
+            });
+
+            ui.checkbox(&mut self.sample_hold, "Sample and hold");
+
+            if self.sample_hold {
+                ui.vertical(|ui| {
+                    ui.label("Rate");
+                    knob(ui, &mut self.rate, 0.1, 100.0);
+                });
+            }
+        });
+
+        // a new seed starts the sequence over
+        if self.seed != prev_seed {
+            self.reset();
+        }
+
+        self.colour != prev_colour || self.seed != prev_seed || self.sample_hold != prev_sample_hold
+    }
+
+    fn params(&self) -> Vec<f64> {
+        vec![self.rate]
+    }
+
+    fn set_param(&mut self, index: usize, value: f64) {
+        if index == 0 {
+            self.rate = value;
+        }
+    }
+}
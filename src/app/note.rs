@@ -1,3 +1,6 @@
+use crate::tuning::Tuning;
+
+#[derive(PartialEq)]
 pub enum Note {
     C,
     CSharp,
@@ -14,24 +17,55 @@ pub enum Note {
 }
 
 impl Note {
-    pub fn freq_base(&self) -> f64 {
+    pub const ALL: [Note; 12] = [
+        Note::C,
+        Note::CSharp,
+        Note::D,
+        Note::DSharp,
+        Note::E,
+        Note::F,
+        Note::FSharp,
+        Note::G,
+        Note::GSharp,
+        Note::A,
+        Note::ASharp,
+        Note::B,
+    ];
+
+    pub fn name(&self) -> &'static str {
         match self {
-            Note::C => 16.35,
-            Note::CSharp => 17.32,
-            Note::D => 18.35,
-            Note::DSharp => 19.45,
-            Note::E => 20.60,
-            Note::F => 21.83,
-            Note::FSharp => 23.12,
-            Note::G => 24.50,
-            Note::GSharp => 25.96,
-            Note::A => 27.50,
-            Note::ASharp => 29.14,
-            Note::B => 30.87,
+            Note::C => "C",
+            Note::CSharp => "C#",
+            Note::D => "D",
+            Note::DSharp => "D#",
+            Note::E => "E",
+            Note::F => "F",
+            Note::FSharp => "F#",
+            Note::G => "G",
+            Note::GSharp => "G#",
+            Note::A => "A",
+            Note::ASharp => "A#",
+            Note::B => "B",
         }
     }
 
-    pub fn freq(&self, octave: i32) -> f64 {
-        self.freq_base() * 2.0f64.powi(octave)
+    /// MIDI note number of the note in `octave`, such as 60 for C4.
+    pub fn midi(&self, octave: i32) -> i32 {
+        (octave + 1) * 12 + Note::ALL.iter().position(|note| note == self).unwrap() as i32
+    }
+
+    pub fn freq(&self, octave: i32, tuning: &Tuning) -> f64 {
+        tuning.key_freq(self.midi(octave))
     }
 }
+
+/// Name of a MIDI note number, such as C4 for 60.
+pub fn midi_name(note: i32) -> String {
+    let octave = note.div_euclid(12) - 1;
+
+    format!(
+        "{}{}",
+        Note::ALL[note.rem_euclid(12) as usize].name(),
+        octave
+    )
+}
@@ -0,0 +1,81 @@
+use crate::arpeggiator::*;
+use crate::dynamics::*;
+use crate::effect_nodes::*;
+use crate::expression::*;
+use crate::fm::*;
+use crate::freq_nodes::*;
+use crate::group::*;
+use crate::input::*;
+use crate::math_nodes::*;
+use crate::modulator::*;
+use crate::node::*;
+use crate::noise::*;
+use crate::quantizer::*;
+use crate::sampler::*;
+use crate::sequencer::*;
+use crate::transport::*;
+use crate::value_node::*;
+use crate::wave::*;
+use crate::waveshaper::*;
+
+/// Names of the nodes that can be added from the side panel.
+pub const NODES: &[&str] = &[
+    "Square Wave",
+    "Sine Wave",
+    "Saw Wave",
+    "FM Operator",
+    "Noise",
+    "Low Pass Filter",
+    "Waveshaper",
+    "Chorus",
+    "Flanger",
+    "Phaser",
+    "Compressor",
+    "Noise Gate",
+    "Math Node",
+    "Expression",
+    "Value",
+    "Freq Shift",
+    "Scale Shift",
+    "Quantizer",
+    "Group",
+    "Oversampler",
+    "Audio Input",
+    "Sampler",
+    "Sequencer",
+    "Transport",
+    "Arpeggiator",
+];
+
+pub fn create(name: &str) -> Option<Box<dyn Node>> {
+    let node: Box<dyn Node> = match name {
+        "Square Wave" => Box::new(SquareWave::new()),
+        "Sine Wave" => Box::new(SineWave::new()),
+        "Saw Wave" => Box::new(SawWave::new()),
+        "FM Operator" => Box::new(FmOperatorNode::new()),
+        "Noise" => Box::new(NoiseNode::new()),
+        "Low Pass Filter" => Box::new(LowPassFilter::new()),
+        "Waveshaper" => Box::new(WaveshaperNode::new()),
+        "Chorus" => Box::new(ChorusNode::new()),
+        "Flanger" => Box::new(FlangerNode::new()),
+        "Phaser" => Box::new(PhaserNode::new()),
+        "Compressor" => Box::new(CompressorNode::new()),
+        "Noise Gate" => Box::new(GateNode::new()),
+        "Math Node" => Box::new(MathNode::new()),
+        "Expression" => Box::new(ExpressionNode::new()),
+        "Value" => Box::new(ValueNode::new()),
+        "Freq Shift" => Box::new(FreqShiftNode::new()),
+        "Scale Shift" => Box::new(ScaleShiftNode::new()),
+        "Quantizer" => Box::new(QuantizerNode::new()),
+        "Group" => Box::new(GroupNode::new()),
+        "Oversampler" => Box::new(GroupNode::oversampled(2)),
+        "Audio Input" => Box::new(AudioInputNode::new()),
+        "Sampler" => Box::new(SamplerNode::new()),
+        "Sequencer" => Box::new(SequencerNode::new()),
+        "Transport" => Box::new(TransportNode::new()),
+        "Arpeggiator" => Box::new(ArpeggiatorNode::new()),
+        _ => return None,
+    };
+
+    Some(node)
+}
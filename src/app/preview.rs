@@ -0,0 +1,177 @@
+use crate::node::*;
+use crate::transport::Transport;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Number of points kept for each preview plot.
+pub const PREVIEW_POINTS: usize = 200;
+
+/// Number of samples evaluated per frame, so long windows don't block the UI.
+const SAMPLES_PER_UPDATE: usize = 2048;
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PreviewSettings {
+    /// Length of the preview window in periods of the preview frequency.
+    pub periods: f64,
+    pub sample_rate: f64,
+}
+
+impl Default for PreviewSettings {
+    fn default() -> Self {
+        Self {
+            periods: 2.0,
+            sample_rate: 44100.0,
+        }
+    }
+}
+
+/// A single evaluation pass of a copy of the whole graph, recording the
+/// displayed output of every node. Each node keeps its state across the
+/// whole window, so stateful nodes are previewed as they would sound.
+#[derive(Clone)]
+pub struct Preview {
+    nodes: NodeManager,
+    freq: f64,
+    sample_length: f64,
+    /// A running transport, so tempo synced nodes are previewed playing.
+    transport: Transport,
+    index: usize,
+    samples: usize,
+    /// Samples per plotted point.
+    bucket: usize,
+    peaks: HashMap<NodeId, f64>,
+    segments: HashMap<NodeId, Vec<f64>>,
+}
+
+impl Preview {
+    pub fn new(nodes: &NodeManager, freq: f64) -> Self {
+        let settings = &nodes.preview_settings;
+
+        let mut nodes = nodes.clone();
+
+        for node in nodes.nodes.values_mut() {
+            node.inner.setup();
+            node.last_sample = None;
+        }
+
+        let samples = (settings.periods / freq * settings.sample_rate).ceil() as usize;
+        let samples = samples.max(PREVIEW_POINTS);
+
+        Self {
+            nodes,
+            freq,
+            sample_length: 1.0 / settings.sample_rate,
+            transport: Transport {
+                playing: true,
+                ..Transport::default()
+            },
+            index: 0,
+            samples,
+            bucket: (samples + PREVIEW_POINTS - 1) / PREVIEW_POINTS,
+            peaks: HashMap::new(),
+            segments: HashMap::new(),
+        }
+    }
+
+    pub fn done(&self) -> bool {
+        self.index >= self.samples
+    }
+
+    /// Evaluates the next part of the window.
+    pub fn update(&mut self) {
+        let end = (self.index + SAMPLES_PER_UPDATE).min(self.samples);
+        let tuning = self.nodes.tuning.clone();
+
+        while self.index < end {
+            let ctx = NodeCtx {
+                freq: self.freq,
+                time: self.index as f64 * self.sample_length,
+                sample_length: self.sample_length,
+                last_sample: 0.0,
+                audio_in: (0.0, 0.0),
+                transport: self.transport,
+                tuning: &tuning,
+            };
+
+            self.transport.advance(self.sample_length);
+
+            let outputs = self.nodes.run_all(&ctx);
+
+            for (id, node) in &self.nodes.nodes {
+                let display = match node.inner.display_out() {
+                    Some(display) => *display,
+                    None => continue,
+                };
+
+                let value = match outputs.get(&(*id, display)) {
+                    Some(SlotValue::None) | None => continue,
+                    Some(value) => value.unwrap_f64(0.0),
+                };
+
+                // each point keeps the sample furthest from zero in its bucket,
+                // so long windows show the envelope of the signal
+                let peak = self.peaks.entry(*id).or_insert(value);
+
+                if value.abs() > peak.abs() {
+                    *peak = value;
+                }
+            }
+
+            self.index += 1;
+
+            if self.index % self.bucket == 0 || self.index == self.samples {
+                for (id, peak) in self.peaks.drain() {
+                    self.segments.entry(id).or_insert(Vec::new()).push(peak);
+                }
+            }
+        }
+    }
+
+    /// Periods of the preview frequency covered by each point.
+    pub fn point_spacing(&self) -> f64 {
+        self.bucket as f64 * self.sample_length * self.freq
+    }
+
+    pub fn into_segments(self) -> HashMap<NodeId, Vec<f64>> {
+        self.segments
+    }
+}
+
+/// Holds the preview being calculated. Cloning a patch doesn't copy it.
+#[derive(Default)]
+pub struct PreviewSlot(pub Option<Box<Preview>>);
+
+impl Clone for PreviewSlot {
+    fn clone(&self) -> Self {
+        Self(None)
+    }
+}
+
+impl NodeManager {
+    /// Starts a new preview pass at `freq`, the previous previews are shown
+    /// until it completes.
+    pub fn calculate_segments(&mut self, freq: f64) {
+        self.preview = PreviewSlot(Some(Box::new(Preview::new(self, freq))));
+    }
+
+    /// Continues the preview pass, returns true while it isn't done.
+    pub fn update_preview(&mut self) -> bool {
+        let done = match &mut self.preview.0 {
+            Some(preview) => {
+                preview.update();
+                preview.done()
+            }
+            None => return false,
+        };
+
+        if done {
+            if let Some(preview) = self.preview.0.take() {
+                self.segment_spacing = preview.point_spacing();
+                self.segments = preview.into_segments();
+            }
+        }
+
+        !done
+    }
+}
@@ -0,0 +1,258 @@
+use crate::node::*;
+use crate::note::Note;
+use eframe::egui::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum ScaleKind {
+    Chromatic,
+    Major,
+    Minor,
+    HarmonicMinor,
+    Dorian,
+    Phrygian,
+    Lydian,
+    Mixolydian,
+    Locrian,
+    MajorPentatonic,
+    MinorPentatonic,
+    Custom,
+}
+
+impl ScaleKind {
+    pub const ALL: [ScaleKind; 12] = [
+        ScaleKind::Chromatic,
+        ScaleKind::Major,
+        ScaleKind::Minor,
+        ScaleKind::HarmonicMinor,
+        ScaleKind::Dorian,
+        ScaleKind::Phrygian,
+        ScaleKind::Lydian,
+        ScaleKind::Mixolydian,
+        ScaleKind::Locrian,
+        ScaleKind::MajorPentatonic,
+        ScaleKind::MinorPentatonic,
+        ScaleKind::Custom,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ScaleKind::Chromatic => "Chromatic",
+            ScaleKind::Major => "Major",
+            ScaleKind::Minor => "Minor",
+            ScaleKind::HarmonicMinor => "Harmonic minor",
+            ScaleKind::Dorian => "Dorian",
+            ScaleKind::Phrygian => "Phrygian",
+            ScaleKind::Lydian => "Lydian",
+            ScaleKind::Mixolydian => "Mixolydian",
+            ScaleKind::Locrian => "Locrian",
+            ScaleKind::MajorPentatonic => "Major pentatonic",
+            ScaleKind::MinorPentatonic => "Minor pentatonic",
+            ScaleKind::Custom => "Custom",
+        }
+    }
+
+    /// Semitones above the root in the scale, `None` for a custom scale.
+    fn intervals(&self) -> Option<&'static [usize]> {
+        Some(match self {
+            ScaleKind::Chromatic => &[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11],
+            ScaleKind::Major => &[0, 2, 4, 5, 7, 9, 11],
+            ScaleKind::Minor => &[0, 2, 3, 5, 7, 8, 10],
+            ScaleKind::HarmonicMinor => &[0, 2, 3, 5, 7, 8, 11],
+            ScaleKind::Dorian => &[0, 2, 3, 5, 7, 9, 10],
+            ScaleKind::Phrygian => &[0, 1, 3, 5, 7, 8, 10],
+            ScaleKind::Lydian => &[0, 2, 4, 6, 7, 9, 11],
+            ScaleKind::Mixolydian => &[0, 2, 4, 5, 7, 9, 10],
+            ScaleKind::Locrian => &[0, 1, 3, 5, 6, 8, 10],
+            ScaleKind::MajorPentatonic => &[0, 2, 4, 7, 9],
+            ScaleKind::MinorPentatonic => &[0, 3, 5, 7, 10],
+            ScaleKind::Custom => return None,
+        })
+    }
+}
+
+/// Snaps a frequency to the closest key of a scale, counted in the keys of
+/// the tuning with 12 to the octave.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct QuantizerNode {
+    scale: ScaleKind,
+    /// Pitch class of the root, 0 for C.
+    root: usize,
+    /// The pitch classes above the root that are in the scale.
+    notes: [bool; 12],
+    /// Seconds to glide between notes.
+    glide: f64,
+    #[serde(skip)]
+    note: Option<f64>,
+    #[serde(skip)]
+    target: f64,
+    #[serde(skip)]
+    step: f64,
+}
+
+impl QuantizerNode {
+    pub fn new() -> Self {
+        let mut node = Self {
+            scale: ScaleKind::Major,
+            root: 0,
+            notes: [false; 12],
+            glide: 0.0,
+            note: None,
+            target: 0.0,
+            step: 0.0,
+        };
+
+        node.set_scale(ScaleKind::Major);
+        node
+    }
+
+    fn set_scale(&mut self, scale: ScaleKind) {
+        self.scale = scale;
+
+        if let Some(intervals) = scale.intervals() {
+            self.notes = [false; 12];
+
+            for interval in intervals {
+                self.notes[*interval] = true;
+            }
+        }
+    }
+
+    /// The closest key to `note` in the scale.
+    fn quantize(&self, note: f64) -> f64 {
+        let base = note.round() as i32;
+
+        (base - 12..=base + 12)
+            .filter(|key| self.notes[(key - self.root as i32).rem_euclid(12) as usize])
+            .map(|key| key as f64)
+            .min_by(|a, b| (a - note).abs().partial_cmp(&(b - note).abs()).unwrap())
+            .unwrap_or(note)
+    }
+
+    fn glide_to(&mut self, target: f64, sample_length: f64) -> f64 {
+        let note = match self.note {
+            Some(note) => note,
+            None => target,
+        };
+
+        if target != self.target {
+            self.target = target;
+            self.step = (target - note).abs() * sample_length / self.glide.max(sample_length);
+        }
+
+        let note = if (target - note).abs() <= self.step {
+            target
+        } else {
+            note + self.step * (target - note).signum()
+        };
+
+        self.note = Some(note);
+        note
+    }
+}
+
+impl Node for QuantizerNode {
+    fn name(&self) -> &str {
+        "Quantizer"
+    }
+
+    fn input_slot_types(&self) -> &[(&'static str, SlotType)] {
+        &[("freq", SlotType::Pitch)]
+    }
+
+    fn output_slot_types(&self) -> &[(&'static str, SlotType)] {
+        &[("freq", SlotType::Pitch)]
+    }
+
+    fn display_out(&self) -> &Option<&str> {
+        &Some("freq")
+    }
+
+    fn setup(&mut self) {
+        self.note = None;
+    }
+
+    fn run(
+        &mut self,
+        ctx: &NodeCtx,
+        input: HashMap<String, SlotValue>,
+    ) -> Vec<(&'static str, SlotValue)> {
+        let freq = match input["freq"] {
+            SlotValue::None => return vec![("freq", SlotValue::None)],
+            freq => freq.unwrap_f64(ctx.freq),
+        };
+
+        let target = self.quantize(ctx.tuning.freq_to_note(freq));
+        let note = self.glide_to(target, ctx.sample_length);
+
+        vec![("freq", SlotValue::Float(ctx.tuning.note_to_freq(note)))]
+    }
+
+    fn ui(&mut self, ui: &mut Ui) -> bool {
+        let prev_scale = self.scale;
+        let prev_root = self.root;
+        let prev_notes = self.notes;
+
+        ui.vertical(|ui| {
+            let mut scale = self.scale;
+
+            for row in ScaleKind::ALL.chunks(3) {
+                ui.horizontal(|ui| {
+                    for kind in row {
+                        ui.radio_value(&mut scale, *kind, kind.name());
+                    }
+                });
+            }
+
+            if scale != self.scale {
+                self.set_scale(scale);
+            }
+
+            ui.label("Root");
+
+            ui.horizontal(|ui| {
+                for (index, note) in Note::ALL.iter().enumerate() {
+                    if ui
+                        .selectable_label(self.root == index, note.name())
+                        .clicked()
+                    {
+                        self.root = index;
+                    }
+                }
+            });
+
+            ui.label("Notes");
+
+            ui.horizontal(|ui| {
+                for (interval, enabled) in self.notes.iter_mut().enumerate() {
+                    let name = Note::ALL[(self.root + interval) % 12].name();
+
+                    if ui.selectable_label(*enabled, name).clicked() {
+                        *enabled = !*enabled;
+                        self.scale = ScaleKind::Custom;
+                    }
+                }
+            });
+
+            ui.horizontal(|ui| {
+                ui.label("Glide");
+                ui.add(DragValue::f64(&mut self.glide).speed(0.005));
+            });
+        });
+
+        self.glide = self.glide.max(0.0).min(5.0);
+
+        self.scale != prev_scale || self.root != prev_root || self.notes != prev_notes
+    }
+
+    fn params(&self) -> Vec<f64> {
+        vec![self.glide]
+    }
+
+    fn set_param(&mut self, index: usize, value: f64) {
+        if index == 0 {
+            self.glide = value;
+        }
+    }
+}
@@ -0,0 +1,321 @@
+use crate::input::read_wav;
+use crate::knob::knob;
+use crate::node::*;
+use eframe::egui::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum PlayMode {
+    OneShot,
+    Loop,
+}
+
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Interpolation {
+    Nearest,
+    Linear,
+    Cubic,
+}
+
+/// A decoded audio file, shared between the copies of the graph.
+pub struct SampleData {
+    pub frames: Vec<(f32, f32)>,
+    pub sample_rate: u32,
+}
+
+impl SampleData {
+    fn frame(&self, index: isize) -> (f64, f64) {
+        let index = index.max(0).min(self.frames.len() as isize - 1) as usize;
+        let (left, right) = self.frames[index];
+
+        (left as f64, right as f64)
+    }
+
+    /// The frame at the fractional `position`.
+    fn read(&self, position: f64, interpolation: Interpolation) -> (f64, f64) {
+        let index = position.floor() as isize;
+        let t = position - index as f64;
+
+        match interpolation {
+            Interpolation::Nearest => self.frame(position.round() as isize),
+            Interpolation::Linear => {
+                let a = self.frame(index);
+                let b = self.frame(index + 1);
+
+                (a.0 + (b.0 - a.0) * t, a.1 + (b.1 - a.1) * t)
+            }
+            Interpolation::Cubic => {
+                let p0 = self.frame(index - 1);
+                let p1 = self.frame(index);
+                let p2 = self.frame(index + 1);
+                let p3 = self.frame(index + 2);
+
+                (
+                    hermite(p0.0, p1.0, p2.0, p3.0, t),
+                    hermite(p0.1, p1.1, p2.1, p3.1, t),
+                )
+            }
+        }
+    }
+}
+
+fn hermite(p0: f64, p1: f64, p2: f64, p3: f64, t: f64) -> f64 {
+    let c1 = 0.5 * (p2 - p0);
+    let c2 = p0 - 2.5 * p1 + 2.0 * p2 - 0.5 * p3;
+    let c3 = 0.5 * (p3 - p0) + 1.5 * (p1 - p2);
+
+    ((c3 * t + c2) * t + c1) * t + p1
+}
+
+/// Plays a WAV file, pitched by the ratio of `freq` to the root note.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SamplerNode {
+    path: String,
+    /// MIDI note the file plays at its original speed.
+    root: f64,
+    /// Start, end and loop points as fractions of the file length.
+    start: f64,
+    end: f64,
+    loop_start: f64,
+    loop_end: f64,
+    mode: PlayMode,
+    interpolation: Interpolation,
+    #[serde(skip)]
+    sample: Option<Arc<SampleData>>,
+    #[serde(skip)]
+    error: Option<String>,
+    #[serde(skip)]
+    position: f64,
+    #[serde(skip)]
+    playing: bool,
+    #[serde(skip)]
+    last_trigger: f64,
+}
+
+impl SamplerNode {
+    pub fn new() -> Self {
+        Self {
+            path: String::new(),
+            root: 60.0,
+            start: 0.0,
+            end: 1.0,
+            loop_start: 0.0,
+            loop_end: 1.0,
+            mode: PlayMode::OneShot,
+            interpolation: Interpolation::Linear,
+            sample: None,
+            error: None,
+            position: 0.0,
+            playing: false,
+            last_trigger: 0.0,
+        }
+    }
+
+    fn load(&mut self) {
+        match read_wav(&self.path) {
+            Ok((frames, sample_rate)) => {
+                self.sample = Some(Arc::new(SampleData {
+                    frames,
+                    sample_rate,
+                }));
+                self.error = None;
+            }
+            Err(err) => {
+                self.sample = None;
+                self.error = Some(err.to_string());
+            }
+        }
+
+        self.setup();
+    }
+
+    /// Start, end, loop start and loop end in frames.
+    fn points(&self, length: f64) -> (f64, f64, f64, f64) {
+        let start = self.start.min(self.end) * length;
+        let end = self.start.max(self.end) * length;
+
+        let loop_start = (self.loop_start * length).max(start).min(end);
+        let loop_end = (self.loop_end * length).max(loop_start).min(end);
+
+        (start, end, loop_start, loop_end)
+    }
+}
+
+impl Node for SamplerNode {
+    fn name(&self) -> &str {
+        "Sampler"
+    }
+
+    fn input_slot_types(&self) -> &[(&'static str, SlotType)] {
+        &[("freq", SlotType::Pitch), ("trigger", SlotType::Trigger)]
+    }
+
+    fn output_slot_types(&self) -> &[(&'static str, SlotType)] {
+        &[("out", SlotType::Audio), ("stereo", SlotType::Stereo)]
+    }
+
+    fn display_out(&self) -> &Option<&str> {
+        &Some("out")
+    }
+
+    /// Starts playing from the start point.
+    fn setup(&mut self) {
+        let length = self.sample.as_ref().map_or(0, |sample| sample.frames.len());
+
+        self.position = self.points(length as f64).0;
+        self.playing = true;
+        self.last_trigger = 0.0;
+    }
+
+    fn run(
+        &mut self,
+        ctx: &NodeCtx,
+        input: HashMap<String, SlotValue>,
+    ) -> Vec<(&'static str, SlotValue)> {
+        let freq = input["freq"].unwrap_f64(ctx.freq);
+        let trigger = input["trigger"].unwrap_f64(0.0);
+
+        let sample = match &self.sample {
+            Some(sample) if !sample.frames.is_empty() => sample.clone(),
+            _ => return vec![("out", SlotValue::None), ("stereo", SlotValue::None)],
+        };
+
+        let (start, end, loop_start, loop_end) = self.points(sample.frames.len() as f64);
+
+        // a rising trigger restarts the sample
+        if trigger > 0.5 && self.last_trigger <= 0.5 {
+            self.position = start;
+            self.playing = true;
+        }
+
+        self.last_trigger = trigger;
+
+        if !self.playing {
+            return vec![
+                ("out", SlotValue::Float(0.0)),
+                ("stereo", SlotValue::Stereo(0.0, 0.0)),
+            ];
+        }
+
+        let (left, right) = sample.read(self.position, self.interpolation);
+
+        let step = freq / ctx.tuning.note_to_freq(self.root)
+            * sample.sample_rate as f64
+            * ctx.sample_length;
+
+        self.position += step;
+
+        match self.mode {
+            PlayMode::Loop if loop_end > loop_start && self.position >= loop_end => {
+                self.position = loop_start + (self.position - loop_end) % (loop_end - loop_start);
+            }
+            _ if self.position >= end => self.playing = false,
+            _ => {}
+        }
+
+        vec![
+            ("out", SlotValue::Float((left + right) * 0.5)),
+            ("stereo", SlotValue::Stereo(left, right)),
+        ]
+    }
+
+    fn ui(&mut self, ui: &mut Ui) -> bool {
+        let mut changed = false;
+
+        ui.vertical(|ui| {
+            ui.set_max_width(200.0);
+
+            ui.horizontal(|ui| {
+                ui.text_edit_singleline(&mut self.path);
+
+                if ui.button("Load").clicked() {
+                    self.load();
+                    changed = true;
+                }
+            });
+
+            match (&self.sample, &self.error) {
+                (_, Some(err)) => {
+                    ui.colored_label(Color32::from_rgb(220, 80, 80), err.as_str());
+                }
+                (Some(sample), None) => {
+                    ui.label(format!(
+                        "{:.2} s, {} Hz",
+                        sample.frames.len() as f64 / sample.sample_rate as f64,
+                        sample.sample_rate
+                    ));
+                }
+                (None, None) => {}
+            }
+
+            ui.horizontal(|ui| {
+                ui.label("Root note");
+                changed = ui.add(DragValue::f64(&mut self.root).speed(0.1)).changed() || changed;
+            });
+
+            ui.horizontal(|ui| {
+                for (label, value) in vec![
+                    ("Start", &mut self.start),
+                    ("End", &mut self.end),
+                    ("Loop start", &mut self.loop_start),
+                    ("Loop end", &mut self.loop_end),
+                ] {
+                    ui.vertical(|ui| {
+                        ui.label(label);
+                        changed = knob(ui, value, 0.0, 1.0) || changed;
+                    });
+                }
+            });
+
+            ui.horizontal(|ui| {
+                let prev = self.mode;
+
+                ui.radio_value(&mut self.mode, PlayMode::OneShot, "One shot");
+                ui.radio_value(&mut self.mode, PlayMode::Loop, "Loop");
+
+                changed = changed || self.mode != prev;
+            });
+
+            ui.horizontal(|ui| {
+                let prev = self.interpolation;
+
+                ui.radio_value(&mut self.interpolation, Interpolation::Nearest, "Nearest");
+                ui.radio_value(&mut self.interpolation, Interpolation::Linear, "Linear");
+                ui.radio_value(&mut self.interpolation, Interpolation::Cubic, "Cubic");
+
+                changed = changed || self.interpolation != prev;
+            });
+        });
+
+        changed
+    }
+
+    fn params(&self) -> Vec<f64> {
+        vec![
+            self.root,
+            self.start,
+            self.end,
+            self.loop_start,
+            self.loop_end,
+        ]
+    }
+
+    fn set_param(&mut self, index: usize, value: f64) {
+        match index {
+            0 => self.root = value,
+            1 => self.start = value,
+            2 => self.end = value,
+            3 => self.loop_start = value,
+            4 => self.loop_end = value,
+            _ => {}
+        }
+    }
+
+    fn load_resources(&mut self) {
+        if self.sample.is_none() && !self.path.is_empty() {
+            self.load();
+        }
+    }
+}
@@ -0,0 +1,302 @@
+use crate::node::*;
+use crossbeam::queue::ArrayQueue;
+use eframe::egui::{plot::*, *};
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+const QUEUE_SIZE: usize = 1 << 14;
+const HISTORY_SIZE: usize = 1 << 13;
+const FFT_SIZE: usize = 2048;
+
+/// Samples sent from the audio thread to the scope without locking.
+pub struct ScopeBuffer {
+    queue: ArrayQueue<f32>,
+    sample_rate: AtomicU32,
+    /// Cleared while the probed output has no signal.
+    signal: AtomicBool,
+}
+
+impl ScopeBuffer {
+    pub fn new() -> Self {
+        Self {
+            queue: ArrayQueue::new(QUEUE_SIZE),
+            sample_rate: AtomicU32::new(44100),
+            signal: AtomicBool::new(true),
+        }
+    }
+
+    /// Called from the audio thread, the sample is dropped if the UI isn't keeping up.
+    pub fn push(&self, sample: f32) {
+        let _ = self.queue.push(sample);
+    }
+
+    pub fn pop(&self) -> Option<f32> {
+        self.queue.pop()
+    }
+
+    pub fn set_sample_rate(&self, sample_rate: u32) {
+        self.sample_rate.store(sample_rate, Ordering::Relaxed);
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate.load(Ordering::Relaxed)
+    }
+
+    pub fn set_signal(&self, signal: bool) {
+        self.signal.store(signal, Ordering::Relaxed);
+    }
+
+    pub fn has_signal(&self) -> bool {
+        self.signal.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Trigger {
+    Free,
+    Rising,
+    Falling,
+}
+
+pub struct Scope {
+    history: VecDeque<f32>,
+    /// Number of samples shown by the scope.
+    pub window: usize,
+    pub trigger: Trigger,
+    pub level: f32,
+    pub frozen: bool,
+    pub show_spectrum: bool,
+    /// Output probed instead of the patch output.
+    pub probe: Option<Probe>,
+}
+
+impl Scope {
+    pub fn new() -> Self {
+        Self {
+            history: VecDeque::with_capacity(HISTORY_SIZE),
+            window: 1024,
+            trigger: Trigger::Rising,
+            level: 0.0,
+            frozen: false,
+            show_spectrum: true,
+            probe: None,
+        }
+    }
+
+    /// Moves the samples written by the audio thread into the history.
+    pub fn update(&mut self, buffer: &ScopeBuffer) {
+        while let Some(sample) = buffer.pop() {
+            if self.frozen {
+                continue;
+            }
+
+            if self.history.len() == HISTORY_SIZE {
+                self.history.pop_front();
+            }
+
+            self.history.push_back(sample);
+        }
+    }
+
+    /// The last `window` samples, starting at a trigger point if one is found.
+    fn triggered(&self, samples: &[f32]) -> Vec<f32> {
+        let window = self.window.min(samples.len());
+        let latest = samples.len() - window;
+
+        let crossing = |i: usize| match self.trigger {
+            Trigger::Free => false,
+            Trigger::Rising => samples[i - 1] < self.level && samples[i] >= self.level,
+            Trigger::Falling => samples[i - 1] > self.level && samples[i] <= self.level,
+        };
+
+        let start = (1..=latest).rev().find(|i| crossing(*i)).unwrap_or(latest);
+
+        samples[start..start + window].to_vec()
+    }
+
+    fn spectrum(samples: &[f32], sample_rate: f64) -> Vec<Value> {
+        if samples.len() < FFT_SIZE {
+            return Vec::new();
+        }
+
+        let samples = &samples[samples.len() - FFT_SIZE..];
+
+        let mut re = samples
+            .iter()
+            .enumerate()
+            .map(|(i, s)| *s as f64 * hann(i, FFT_SIZE))
+            .collect::<Vec<_>>();
+        let mut im = vec![0.0; FFT_SIZE];
+
+        fft(&mut re, &mut im);
+
+        (1..FFT_SIZE / 2)
+            .map(|i| {
+                let magnitude = (re[i] * re[i] + im[i] * im[i]).sqrt() * 4.0 / FFT_SIZE as f64;
+                let freq = i as f64 * sample_rate / FFT_SIZE as f64;
+
+                Value::new(freq.log10(), 20.0 * (magnitude + 1e-9).log10())
+            })
+            .collect()
+    }
+
+    /// Shows the scope and spectrum, returns true if the probed output changed.
+    pub fn ui(&mut self, ui: &mut Ui, nodes: &mut NodeManager, buffer: &ScopeBuffer) -> bool {
+        let samples = self.history.iter().cloned().collect::<Vec<_>>();
+        let prev_probe = self.probe.clone();
+
+        ui.horizontal(|ui| {
+            ui.radio_value(&mut self.trigger, Trigger::Free, "Free");
+            ui.radio_value(&mut self.trigger, Trigger::Rising, "Rising");
+            ui.radio_value(&mut self.trigger, Trigger::Falling, "Falling");
+        });
+
+        ui.horizontal(|ui| {
+            let mut window = self.window as f64;
+
+            ui.label("Window:");
+            ui.add(DragValue::f64(&mut window).speed(8.0));
+            ui.label("Level:");
+            ui.add(DragValue::f32(&mut self.level).speed(0.01));
+
+            self.window = (window as usize).max(16).min(HISTORY_SIZE);
+        });
+
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut self.frozen, "Freeze");
+            ui.checkbox(&mut self.show_spectrum, "Spectrum");
+        });
+
+        let curve = Curve::from_values_iter(
+            self.triggered(&samples)
+                .into_iter()
+                .enumerate()
+                .map(|(i, s)| Value::new(i as f64, s as f64)),
+        );
+
+        ui.add(
+            Plot::default()
+                .curve(curve)
+                .symmetrical_y_bounds(true)
+                .view_aspect(2.0),
+        );
+
+        // the probed node may have been removed, or its group isn't running
+        if self.probe.is_some() && !buffer.has_signal() {
+            ui.colored_label(
+                Color32::from_rgb(220, 80, 80),
+                "The probed output has no signal",
+            );
+        }
+
+        if self.show_spectrum {
+            let stroke = Stroke::new(1.0, Color32::from_gray(100));
+
+            let spectrum = Self::spectrum(&samples, buffer.sample_rate() as f64);
+            let curve = Curve::from_values_iter(spectrum.into_iter());
+
+            ui.label("Spectrum (dB over log10 Hz)");
+            ui.add(
+                Plot::default()
+                    .curve(curve)
+                    .hline(HLine::new(0.0, stroke))
+                    .hline(HLine::new(-96.0, stroke))
+                    .view_aspect(2.0),
+            );
+        }
+
+        ui.collapsing("Probe", |ui| {
+            ui.radio_value(&mut self.probe, None, "Patch output");
+
+            Self::probe_ui(ui, &mut self.probe, nodes, &mut Vec::new(), "");
+        });
+
+        self.probe != prev_probe
+    }
+
+    /// Lists the outputs of the nodes in `nodes` and in its subpatches, which
+    /// are at `path` and named after `prefix`.
+    fn probe_ui(
+        ui: &mut Ui,
+        selected: &mut Option<Probe>,
+        nodes: &mut NodeManager,
+        path: &mut Vec<NodeId>,
+        prefix: &str,
+    ) {
+        let mut ids = nodes.nodes.keys().cloned().collect::<Vec<_>>();
+        ids.sort_by_key(|id| id.0);
+
+        for id in ids {
+            let node = nodes.nodes.get_mut(&id).unwrap();
+            let name = format!("{}{}", prefix, node.inner.name());
+
+            for (slot, _ty) in node.inner.output_slot_types() {
+                let probe = Some(Probe {
+                    path: path.clone(),
+                    id,
+                    slot: slot.to_string(),
+                });
+
+                ui.radio_value(selected, probe, format!("{}: {}", name, slot));
+            }
+
+            if let Some(subpatch) = node.inner.subpatch() {
+                path.push(id);
+                Self::probe_ui(ui, selected, subpatch, path, &format!("{} / ", name));
+                path.pop();
+            }
+        }
+    }
+}
+
+fn hann(i: usize, size: usize) -> f64 {
+    0.5 - 0.5 * (2.0 * std::f64::consts::PI * i as f64 / (size - 1) as f64).cos()
+}
+
+/// In place radix-2 FFT, the length of `re` and `im` must be a power of two.
+pub fn fft(re: &mut [f64], im: &mut [f64]) {
+    let n = re.len();
+
+    let mut j = 0;
+
+    for i in 1..n {
+        let mut bit = n >> 1;
+
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+
+        j |= bit;
+
+        if i < j {
+            re.swap(i, j);
+            im.swap(i, j);
+        }
+    }
+
+    let mut len = 2;
+
+    while len <= n {
+        let angle = -2.0 * std::f64::consts::PI / len as f64;
+
+        for start in (0..n).step_by(len) {
+            for k in 0..len / 2 {
+                let (sin, cos) = (angle * k as f64).sin_cos();
+
+                let a = start + k;
+                let b = a + len / 2;
+
+                let t_re = re[b] * cos - im[b] * sin;
+                let t_im = re[b] * sin + im[b] * cos;
+
+                re[b] = re[a] - t_re;
+                im[b] = im[a] - t_im;
+                re[a] += t_re;
+                im[a] += t_im;
+            }
+        }
+
+        len <<= 1;
+    }
+}
@@ -0,0 +1,311 @@
+use crate::node::*;
+use crate::note::*;
+use eframe::egui::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+pub const MAX_STEPS: usize = 32;
+
+/// Parameters before the velocities of the steps. The notes, gates and
+/// slides aren't parameters, since ramping them would sweep through the
+/// values between, so editing them sends the graph again.
+const GLOBAL_PARAMS: usize = 3;
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Step {
+    /// MIDI note number.
+    pub note: f64,
+    pub gate: bool,
+    pub velocity: f64,
+    /// Glide into this step from the previous one, holding the gate between them.
+    pub slide: bool,
+}
+
+impl Step {
+    fn new(note: f64) -> Self {
+        Self {
+            note,
+            gate: true,
+            velocity: 1.0,
+            slide: false,
+        }
+    }
+}
+
+/// Plays a pattern of steps, advanced by its own clock, the transport or the
+/// `clock` input.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SequencerNode {
+    steps: Vec<Step>,
+    bpm: f64,
+    /// Follow the global transport instead of `bpm` when nothing is connected
+    /// to `clock`.
+    #[serde(default)]
+    sync: bool,
+    /// Steps per beat.
+    division: f64,
+    /// Fraction of a step the gate stays open.
+    gate_length: f64,
+    /// Fraction of a step a slide takes.
+    slide_time: f64,
+    #[serde(skip)]
+    current: usize,
+    /// Position in the current step from 0 to 1.
+    #[serde(skip)]
+    phase: f64,
+    #[serde(skip)]
+    last_clock: f64,
+    #[serde(skip)]
+    last_reset: f64,
+    /// Samples between the last two clock pulses.
+    #[serde(skip)]
+    clock_period: f64,
+    #[serde(skip)]
+    since_clock: f64,
+}
+
+impl SequencerNode {
+    pub fn new() -> Self {
+        Self {
+            steps: [60.0, 63.0, 67.0, 72.0, 60.0, 63.0, 67.0, 70.0]
+                .iter()
+                .map(|note| Step::new(*note))
+                .collect(),
+            bpm: 120.0,
+            sync: false,
+            division: 4.0,
+            gate_length: 0.5,
+            slide_time: 0.3,
+            current: 0,
+            phase: 0.0,
+            last_clock: 0.0,
+            last_reset: 0.0,
+            clock_period: 0.0,
+            since_clock: 0.0,
+        }
+    }
+
+    fn advance(&mut self) {
+        self.current = (self.current + 1) % self.steps.len();
+        self.phase = 0.0;
+    }
+
+    /// Returns true if the note, gate or slide of the step changed.
+    fn step_ui(ui: &mut Ui, step: &mut Step) -> bool {
+        let prev = (step.note, step.gate, step.slide);
+
+        ui.vertical(|ui| {
+            ui.label(midi_name(step.note.round() as i32));
+            ui.add(DragValue::f64(&mut step.note).speed(0.2));
+            ui.checkbox(&mut step.gate, "");
+            ui.add(DragValue::f64(&mut step.velocity).speed(0.01));
+            ui.checkbox(&mut step.slide, "");
+
+            step.note = step.note.round().max(0.0).min(127.0);
+            step.velocity = step.velocity.max(0.0).min(1.0);
+        });
+
+        (step.note, step.gate, step.slide) != prev
+    }
+}
+
+impl Node for SequencerNode {
+    fn name(&self) -> &str {
+        "Sequencer"
+    }
+
+    fn input_slot_types(&self) -> &[(&'static str, SlotType)] {
+        &[("clock", SlotType::Trigger), ("reset", SlotType::Trigger)]
+    }
+
+    fn output_slot_types(&self) -> &[(&'static str, SlotType)] {
+        &[
+            ("freq", SlotType::Pitch),
+            ("gate", SlotType::Gate),
+            ("velocity", SlotType::Control),
+        ]
+    }
+
+    fn display_out(&self) -> &Option<&str> {
+        &Some("gate")
+    }
+
+    fn setup(&mut self) {
+        self.current = 0;
+        self.phase = 0.0;
+        self.last_clock = 0.0;
+        self.last_reset = 0.0;
+        self.since_clock = 0.0;
+    }
+
+    fn run(
+        &mut self,
+        ctx: &NodeCtx,
+        input: HashMap<String, SlotValue>,
+    ) -> Vec<(&'static str, SlotValue)> {
+        if self.steps.is_empty() {
+            return vec![
+                ("freq", SlotValue::None),
+                ("gate", SlotValue::Float(0.0)),
+                ("velocity", SlotValue::Float(0.0)),
+            ];
+        }
+
+        // only the rising edge resets, so a held reset doesn't stop the steps
+        let reset = input["reset"].unwrap_f64(0.0);
+
+        if reset > 0.5 && self.last_reset <= 0.5 {
+            self.current = 0;
+            self.phase = 0.0;
+        }
+
+        self.last_reset = reset;
+
+        let mut stopped = false;
+
+        match input["clock"] {
+            // the step and phase follow the position of the transport
+            SlotValue::None if self.sync => {
+                let position = ctx.transport.beats.max(0.0) * self.division;
+
+                self.current = position.floor() as usize % self.steps.len();
+                self.phase = position.fract();
+                stopped = !ctx.transport.playing;
+            }
+            SlotValue::None => {
+                self.phase += ctx.sample_length * self.bpm / 60.0 * self.division;
+
+                if self.phase >= 1.0 {
+                    self.advance();
+                }
+            }
+            // each rising edge of the clock moves to the next step, the
+            // period between edges gives the length of a step
+            clock => {
+                let clock = clock.unwrap_f64(0.0);
+                self.since_clock += 1.0;
+
+                if clock > 0.5 && self.last_clock <= 0.5 {
+                    self.clock_period = self.since_clock;
+                    self.since_clock = 0.0;
+                    self.advance();
+                }
+
+                self.last_clock = clock;
+
+                if self.clock_period > 0.0 {
+                    self.phase = (self.since_clock / self.clock_period).min(1.0);
+                }
+            }
+        }
+
+        let len = self.steps.len();
+        let step = &self.steps[self.current];
+        let prev = &self.steps[(self.current + len - 1) % len];
+        let next = &self.steps[(self.current + 1) % len];
+
+        let note = if step.slide && self.slide_time > 0.0 {
+            let t = (self.phase / self.slide_time).min(1.0);
+            prev.note + (step.note - prev.note) * t
+        } else {
+            step.note
+        };
+
+        // a sliding step after this one keeps the gate open
+        let held = next.slide && next.gate;
+        let gate = !stopped && step.gate && (self.phase < self.gate_length || held);
+
+        vec![
+            ("freq", SlotValue::Float(ctx.tuning.note_to_freq(note))),
+            ("gate", SlotValue::Float(if gate { 1.0 } else { 0.0 })),
+            ("velocity", SlotValue::Float(step.velocity)),
+        ]
+    }
+
+    fn ui(&mut self, ui: &mut Ui) -> bool {
+        let prev_len = self.steps.len();
+        let prev_sync = self.sync;
+        let prev_division = self.division;
+        let mut pattern_changed = false;
+
+        ui.vertical(|ui| {
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut self.sync, "Sync to transport");
+            });
+
+            ui.horizontal(|ui| {
+                if !self.sync {
+                    ui.label("BPM");
+                    ui.add(DragValue::f64(&mut self.bpm).speed(0.5));
+                }
+
+                ui.label("Steps per beat");
+                ui.add(DragValue::f64(&mut self.division).speed(0.05));
+            });
+
+            ui.horizontal(|ui| {
+                ui.label("Gate");
+                ui.add(DragValue::f64(&mut self.gate_length).speed(0.01));
+                ui.label("Slide");
+                ui.add(DragValue::f64(&mut self.slide_time).speed(0.01));
+            });
+
+            ui.horizontal(|ui| {
+                ui.label(format!("Steps: {}", self.steps.len()));
+
+                if ui.button("+").clicked() && self.steps.len() < MAX_STEPS {
+                    let note = self.steps.last().map_or(60.0, |step| step.note);
+                    self.steps.push(Step::new(note));
+                }
+
+                if ui.button("-").clicked() && self.steps.len() > 1 {
+                    self.steps.pop();
+                }
+            });
+
+            ui.horizontal(|ui| {
+                ui.vertical(|ui| {
+                    ui.label("");
+                    ui.label("Note");
+                    ui.label("Gate");
+                    ui.label("Vel");
+                    ui.label("Slide");
+                });
+
+                for step in &mut self.steps {
+                    pattern_changed = Self::step_ui(ui, step) || pattern_changed;
+                }
+            });
+        });
+
+        self.bpm = self.bpm.max(1.0).min(999.0);
+        self.division = self.division.max(0.25).min(16.0);
+        self.gate_length = self.gate_length.max(0.0).min(1.0);
+        self.slide_time = self.slide_time.max(0.0).min(1.0);
+
+        pattern_changed
+            || self.steps.len() != prev_len
+            || self.sync != prev_sync
+            || self.division != prev_division
+    }
+
+    fn params(&self) -> Vec<f64> {
+        let mut params = vec![self.bpm, self.gate_length, self.slide_time];
+        params.extend(self.steps.iter().map(|step| step.velocity));
+
+        params
+    }
+
+    fn set_param(&mut self, index: usize, value: f64) {
+        match index {
+            0 => self.bpm = value,
+            1 => self.gate_length = value,
+            2 => self.slide_time = value,
+            _ => {
+                if let Some(step) = self.steps.get_mut(index - GLOBAL_PARAMS) {
+                    step.velocity = value;
+                }
+            }
+        }
+    }
+}
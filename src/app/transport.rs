@@ -0,0 +1,235 @@
+use crate::node::*;
+use eframe::egui::*;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+pub const TICKS_PER_BEAT: f64 = 960.0;
+
+/// Musical time shared by every node through `NodeCtx`.
+#[derive(Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Transport {
+    pub playing: bool,
+    pub bpm: f64,
+    pub beats_per_bar: u32,
+    /// Note value of a beat, 4 for quarter notes.
+    pub beat_unit: u32,
+    /// Beats since the start, not saved.
+    #[serde(skip)]
+    pub beats: f64,
+}
+
+impl Default for Transport {
+    fn default() -> Self {
+        Self {
+            playing: false,
+            bpm: 120.0,
+            beats_per_bar: 4,
+            beat_unit: 4,
+            beats: 0.0,
+        }
+    }
+}
+
+pub enum TransportCommand {
+    Play,
+    Stop,
+    /// Moves back to the start of the first bar.
+    Rewind,
+    SetTempo(f64),
+    SetSignature(u32, u32),
+}
+
+impl Transport {
+    pub fn apply(&mut self, command: TransportCommand) {
+        match command {
+            TransportCommand::Play => self.playing = true,
+            TransportCommand::Stop => self.playing = false,
+            TransportCommand::Rewind => self.beats = 0.0,
+            TransportCommand::SetTempo(bpm) => self.bpm = bpm,
+            TransportCommand::SetSignature(beats_per_bar, beat_unit) => {
+                self.beats_per_bar = beats_per_bar;
+                self.beat_unit = beat_unit;
+            }
+        }
+    }
+
+    pub fn advance(&mut self, seconds: f64) {
+        if self.playing {
+            self.beats += seconds * self.bpm / 60.0;
+        }
+    }
+
+    /// Seconds per beat.
+    pub fn beat_length(&self) -> f64 {
+        60.0 / self.bpm
+    }
+
+    /// Bar, beat in the bar and tick in the beat, counting bars and beats from 1.
+    pub fn position(&self) -> (u64, u32, u32) {
+        let beats = self.beats.max(0.0);
+        let whole = beats.floor() as u64;
+        let per_bar = self.beats_per_bar.max(1) as u64;
+
+        (
+            whole / per_bar + 1,
+            (whole % per_bar) as u32 + 1,
+            (beats.fract() * TICKS_PER_BEAT) as u32,
+        )
+    }
+
+    /// Position in the current bar from 0 to 1.
+    pub fn bar_phase(&self) -> f64 {
+        (self.beats / self.beats_per_bar.max(1) as f64).fract()
+    }
+
+    /// Shows the transport controls, returning the commands to send to the driver.
+    pub fn ui(&mut self, ui: &mut Ui, position: f64) -> Vec<TransportCommand> {
+        let mut commands = Vec::new();
+
+        ui.horizontal(|ui| {
+            let label = if self.playing { "Stop" } else { "Play" };
+
+            if ui.button(label).clicked() {
+                self.playing = !self.playing;
+
+                commands.push(if self.playing {
+                    TransportCommand::Play
+                } else {
+                    TransportCommand::Stop
+                });
+            }
+
+            if ui.button("Rewind").clicked() {
+                commands.push(TransportCommand::Rewind);
+            }
+        });
+
+        let (bar, beat, tick) = Transport {
+            beats: position,
+            ..*self
+        }
+        .position();
+
+        ui.label(format!("{}.{}.{:03}", bar, beat, tick));
+
+        ui.horizontal(|ui| {
+            ui.label("BPM");
+
+            let prev = self.bpm;
+            ui.add(DragValue::f64(&mut self.bpm).speed(0.5));
+            self.bpm = self.bpm.max(1.0).min(999.0);
+
+            if self.bpm != prev {
+                commands.push(TransportCommand::SetTempo(self.bpm));
+            }
+        });
+
+        ui.horizontal(|ui| {
+            let prev = (self.beats_per_bar, self.beat_unit);
+
+            let mut beats_per_bar = self.beats_per_bar as f64;
+            let mut beat_unit = self.beat_unit as f64;
+
+            ui.add(DragValue::f64(&mut beats_per_bar).speed(0.1));
+            ui.label("/");
+            ui.add(DragValue::f64(&mut beat_unit).speed(0.1));
+
+            self.beats_per_bar = (beats_per_bar as u32).max(1).min(32);
+            self.beat_unit = (beat_unit as u32).max(1).min(32);
+
+            if (self.beats_per_bar, self.beat_unit) != prev {
+                commands.push(TransportCommand::SetSignature(
+                    self.beats_per_bar,
+                    self.beat_unit,
+                ));
+            }
+        });
+
+        commands
+    }
+
+    /// Commands bringing a new driver in line with these settings.
+    pub fn sync_commands(&self) -> Vec<TransportCommand> {
+        vec![
+            TransportCommand::SetTempo(self.bpm),
+            TransportCommand::SetSignature(self.beats_per_bar, self.beat_unit),
+            if self.playing {
+                TransportCommand::Play
+            } else {
+                TransportCommand::Stop
+            },
+        ]
+    }
+}
+
+/// Position of the driver's transport in beats, read by the UI.
+pub struct TransportPosition(AtomicU64);
+
+impl TransportPosition {
+    pub fn new() -> Self {
+        Self(AtomicU64::new(0.0f64.to_bits()))
+    }
+
+    pub fn set(&self, beats: f64) {
+        self.0.store(beats.to_bits(), Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> f64 {
+        f64::from_bits(self.0.load(Ordering::Relaxed))
+    }
+}
+
+/// Outputs the transport as signals, for syncing nodes that have no tempo
+/// settings of their own.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct TransportNode {
+    /// Clock pulses per beat.
+    division: f64,
+}
+
+impl TransportNode {
+    pub fn new() -> Self {
+        Self { division: 4.0 }
+    }
+}
+
+crate::node! {
+    TransportNode => "Transport"(&mut self, ctx: &NodeCtx) -> [clock: Trigger, beat: Control, bar: Control, playing: Gate, bpm: Control] {
+        let transport = &ctx.transport;
+
+        // the clock is high for the first half of each division
+        let pulse = (transport.beats * self.division).fract() < 0.5;
+
+        clock = SlotValue::Float(if transport.playing && pulse { 1.0 } else { 0.0 });
+        beat = SlotValue::Float(transport.beats.fract());
+        bar = SlotValue::Float(transport.bar_phase());
+        playing = SlotValue::Float(if transport.playing { 1.0 } else { 0.0 });
+        bpm = SlotValue::Float(transport.bpm);
+    }
+
+    display clock;
+
+    fn params(&self) -> Vec<f64> {
+        vec![self.division]
+    }
+
+    fn set_param(&mut self, index: usize, value: f64) {
+        if index == 0 {
+            self.division = value;
+        }
+    }
+
+    fn ui(&mut self, ui: &mut Ui) -> bool {
+        let prev = self.division;
+
+        ui.horizontal(|ui| {
+            ui.label("Pulses per beat");
+            ui.add(DragValue::f64(&mut self.division).speed(0.05));
+        });
+
+        self.division = self.division.max(0.25).min(16.0);
+
+        self.division != prev
+    }
+}
@@ -0,0 +1,356 @@
+use eframe::egui::*;
+use serde::{Deserialize, Serialize};
+
+/// Reference pitches offered in the settings.
+const REFERENCE_PRESETS: [f64; 3] = [432.0, 440.0, 442.0];
+
+/// The pitches of a scale, as read from a Scala .scl file.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Scale {
+    pub description: String,
+    /// Degrees after the unison in cents, the last one is the period the
+    /// scale repeats at.
+    pub cents: Vec<f64>,
+}
+
+impl Scale {
+    pub fn equal(steps: usize) -> Self {
+        Self {
+            description: format!("{} tone equal temperament", steps),
+            cents: (1..=steps)
+                .map(|step| step as f64 * 1200.0 / steps as f64)
+                .collect(),
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<Self, anyhow::Error> {
+        let mut lines = content_lines(text);
+
+        let description = lines.next().unwrap_or_default().to_string();
+        let count: usize = lines
+            .next()
+            .ok_or_else(|| anyhow::anyhow!("Missing number of notes"))?
+            .split_whitespace()
+            .next()
+            .unwrap_or_default()
+            .parse()?;
+
+        let cents = lines
+            .take(count)
+            .map(parse_pitch)
+            .collect::<Result<Vec<f64>, _>>()?;
+
+        if cents.len() != count {
+            anyhow::bail!("Expected {} notes, found {}", count, cents.len());
+        }
+
+        if count == 0 {
+            anyhow::bail!("The scale has no notes");
+        }
+
+        Ok(Self { description, cents })
+    }
+
+    /// Cents of `degree` above the unison, following the scale through its
+    /// periods in both directions.
+    pub fn degree_cents(&self, degree: i32) -> f64 {
+        let len = self.cents.len() as i32;
+        let period = self.cents[self.cents.len() - 1];
+        let index = degree.rem_euclid(len);
+
+        let cents = if index == 0 {
+            0.0
+        } else {
+            self.cents[index as usize - 1]
+        };
+
+        degree.div_euclid(len) as f64 * period + cents
+    }
+}
+
+/// Lines of a Scala file that aren't comments.
+fn content_lines(text: &str) -> impl Iterator<Item = &str> {
+    text.lines()
+        .filter(|line| !line.starts_with('!'))
+        .map(|line| line.trim())
+}
+
+/// A pitch line of a .scl file, cents if it has a period and a ratio otherwise.
+fn parse_pitch(line: &str) -> Result<f64, anyhow::Error> {
+    let pitch = line.split_whitespace().next().unwrap_or_default();
+
+    if pitch.contains('.') {
+        return Ok(pitch.parse()?);
+    }
+
+    let ratio = match pitch.find('/') {
+        Some(index) => pitch[..index].parse::<f64>()? / pitch[index + 1..].parse::<f64>()?,
+        None => pitch.parse()?,
+    };
+
+    if !(ratio > 0.0) || !ratio.is_finite() {
+        anyhow::bail!("Invalid ratio {}", pitch);
+    }
+
+    Ok(1200.0 * ratio.log2())
+}
+
+/// How MIDI notes map to the degrees of a scale, as read from a Scala .kbm
+/// file. The note range of the file is not used.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct KeyboardMap {
+    /// Degrees of the notes in one repetition of the map, `None` for keys
+    /// that are left out. Empty for a linear mapping.
+    pub mapping: Vec<Option<i32>>,
+    /// Note the first entry of the map, the unison of the scale, is on.
+    pub middle_note: i32,
+    pub reference_note: i32,
+    pub reference_freq: f64,
+    /// Degree each repetition of the map is transposed by.
+    pub octave_degree: i32,
+}
+
+impl KeyboardMap {
+    /// Consecutive notes on consecutive degrees, with the scale starting on
+    /// middle C and A4 at 440 Hz.
+    pub fn linear() -> Self {
+        Self {
+            mapping: Vec::new(),
+            middle_note: 60,
+            reference_note: 69,
+            reference_freq: 440.0,
+            octave_degree: 0,
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<Self, anyhow::Error> {
+        let mut lines = content_lines(text).filter(|line| !line.is_empty());
+
+        let mut field = |name: &str| {
+            lines
+                .next()
+                .and_then(|line| line.split_whitespace().next())
+                .map(|value| value.to_string())
+                .ok_or_else(|| anyhow::anyhow!("Missing {}", name))
+        };
+
+        let size: usize = field("map size")?.parse()?;
+        let _first_note: i32 = field("first note")?.parse()?;
+        let _last_note: i32 = field("last note")?.parse()?;
+        let middle_note = field("middle note")?.parse()?;
+        let reference_note = field("reference note")?.parse()?;
+        let reference_freq: f64 = field("reference frequency")?.parse()?;
+        let octave_degree = field("octave degree")?.parse()?;
+
+        let mut mapping = Vec::with_capacity(size);
+
+        // missing entries at the end are left out keys
+        for _ in 0..size {
+            mapping.push(match field("mapping") {
+                Ok(entry) if entry == "x" => None,
+                Ok(entry) => Some(entry.parse()?),
+                Err(_) => None,
+            });
+        }
+
+        if !(reference_freq > 0.0) {
+            anyhow::bail!("Invalid reference frequency {}", reference_freq);
+        }
+
+        Ok(Self {
+            mapping,
+            middle_note,
+            reference_note,
+            reference_freq,
+            octave_degree,
+        })
+    }
+
+    /// The scale degree of `note`, `None` if the map leaves it out.
+    fn degree(&self, note: i32, scale: &Scale) -> Option<i32> {
+        let offset = note - self.middle_note;
+
+        if self.mapping.is_empty() {
+            return Some(offset);
+        }
+
+        let size = self.mapping.len() as i32;
+        let octave_degree = if self.octave_degree > 0 {
+            self.octave_degree
+        } else {
+            scale.cents.len() as i32
+        };
+
+        self.mapping[offset.rem_euclid(size) as usize]
+            .map(|degree| offset.div_euclid(size) * octave_degree + degree)
+    }
+}
+
+/// Converts between note numbers and frequencies for a scale and keyboard
+/// map, the default is 12 tone equal temperament with A4 at 440 Hz.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Tuning {
+    pub scale: Scale,
+    pub map: KeyboardMap,
+}
+
+impl Default for Tuning {
+    fn default() -> Self {
+        Self {
+            scale: Scale::equal(12),
+            map: KeyboardMap::linear(),
+        }
+    }
+}
+
+impl Tuning {
+    /// Cents of `note` above the unison of the scale. Left out keys sound the
+    /// closest mapped key below them.
+    fn cents(&self, note: i32) -> f64 {
+        let size = self.map.mapping.len().max(1) as i32;
+
+        (0..size)
+            .find_map(|offset| self.map.degree(note - offset, &self.scale))
+            .map_or(0.0, |degree| self.scale.degree_cents(degree))
+    }
+
+    pub fn key_freq(&self, note: i32) -> f64 {
+        let cents = self.cents(note) - self.cents(self.map.reference_note);
+
+        self.map.reference_freq * 2.0f64.powf(cents / 1200.0)
+    }
+
+    /// Frequency of a note number, fractional notes glide between the two
+    /// keys around them.
+    pub fn note_to_freq(&self, note: f64) -> f64 {
+        let key = note.floor();
+        let low = self.key_freq(key as i32);
+
+        if key == note {
+            return low;
+        }
+
+        let high = self.key_freq(key as i32 + 1);
+
+        low * (high / low).powf(note - key)
+    }
+
+    /// The fractional note number of a frequency, the inverse of
+    /// `note_to_freq`.
+    pub fn freq_to_note(&self, freq: f64) -> f64 {
+        if !(freq > 0.0) {
+            return 0.0;
+        }
+
+        // keys rise with the note number, so the one below can be searched for
+        let (mut low, mut high) = (-256, 384);
+
+        while high - low > 1 {
+            let mid = (low + high) / 2;
+
+            if self.key_freq(mid) <= freq {
+                low = mid;
+            } else {
+                high = mid;
+            }
+        }
+
+        let low_freq = self.key_freq(low);
+        let high_freq = self.key_freq(high);
+
+        if high_freq > low_freq {
+            low as f64 + (freq / low_freq).ln() / (high_freq / low_freq).ln()
+        } else {
+            low as f64
+        }
+    }
+}
+
+/// The tuning used by the app along with the files it was loaded from.
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TuningSettings {
+    pub tuning: Tuning,
+    pub scale_path: String,
+    pub map_path: String,
+    #[serde(skip)]
+    error: Option<String>,
+}
+
+impl TuningSettings {
+    fn load(&mut self) -> Result<(), anyhow::Error> {
+        let scale = if self.scale_path.is_empty() {
+            Scale::equal(12)
+        } else {
+            Scale::parse(&std::fs::read_to_string(&self.scale_path)?)?
+        };
+
+        let map = if self.map_path.is_empty() {
+            KeyboardMap {
+                reference_freq: self.tuning.map.reference_freq,
+                ..KeyboardMap::linear()
+            }
+        } else {
+            KeyboardMap::parse(&std::fs::read_to_string(&self.map_path)?)?
+        };
+
+        self.tuning = Tuning { scale, map };
+
+        Ok(())
+    }
+
+    /// Shows the settings, returns true if the tuning changed.
+    pub fn ui(&mut self, ui: &mut Ui) -> bool {
+        let mut changed = false;
+
+        ui.label(&self.tuning.scale.description);
+
+        ui.horizontal(|ui| {
+            ui.label("Scale (.scl):");
+            ui.text_edit_singleline(&mut self.scale_path);
+        });
+
+        ui.horizontal(|ui| {
+            ui.label("Map (.kbm):");
+            ui.text_edit_singleline(&mut self.map_path);
+        });
+
+        ui.horizontal(|ui| {
+            if ui.button("Load").clicked() {
+                self.error = self.load().err().map(|err| err.to_string());
+                changed = self.error.is_none();
+            }
+
+            if ui.button("Reset").clicked() {
+                self.scale_path.clear();
+                self.map_path.clear();
+                self.tuning = Tuning::default();
+                self.error = None;
+                changed = true;
+            }
+        });
+
+        if let Some(err) = &self.error {
+            ui.colored_label(Color32::from_rgb(220, 80, 80), err.as_str());
+        }
+
+        ui.horizontal(|ui| {
+            let map = &mut self.tuning.map;
+            let prev = map.reference_freq;
+
+            ui.label(format!("{} =", crate::note::midi_name(map.reference_note)));
+            ui.add(DragValue::f64(&mut map.reference_freq).speed(0.1));
+
+            for freq in &REFERENCE_PRESETS {
+                if ui.button(format!("{}", freq)).clicked() {
+                    map.reference_freq = *freq;
+                }
+            }
+
+            map.reference_freq = map.reference_freq.max(1.0).min(20000.0);
+            changed = changed || map.reference_freq != prev;
+        });
+
+        changed
+    }
+}
@@ -10,10 +10,20 @@ impl ValueNode {
 }
 
 crate::node! {
-    ValueNode => "Value"(&mut self, ctx: &NodeCtx) -> [out: Float] {
+    ValueNode => "Value"(&mut self, ctx: &NodeCtx) -> [out: Control] {
         out = SlotValue::Float(self.0);
     }
 
+    fn params(&self) -> Vec<f64> {
+        vec![self.0]
+    }
+
+    fn set_param(&mut self, index: usize, value: f64) {
+        if index == 0 {
+            self.0 = value;
+        }
+    }
+
     fn ui(&mut self, ui: &mut Ui) -> bool {
         let prev = self.0;
 
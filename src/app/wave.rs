@@ -63,11 +63,11 @@ impl Node for SquareWave {
     }
 
     fn input_slot_types(&self) -> &[(&'static str, SlotType)] {
-        &[("freq", SlotType::Float)]
+        &[("freq", SlotType::Pitch)]
     }
 
     fn output_slot_types(&self) -> &[(&'static str, SlotType)] {
-        &[("freq_out", SlotType::Float), ("out", SlotType::Float)]
+        &[("freq_out", SlotType::Pitch), ("out", SlotType::Audio)]
     }
 
     fn display_out(&self) -> &Option<&str> {
@@ -75,7 +75,7 @@ impl Node for SquareWave {
     }
 
     fn run(
-        &self,
+        &mut self,
         ctx: &NodeCtx,
         input: HashMap<String, SlotValue>,
     ) -> Vec<(&'static str, SlotValue)> {
@@ -90,6 +90,16 @@ impl Node for SquareWave {
     fn ui(&mut self, ui: &mut Ui) -> bool {
         WaveGenerator::ui(self, ui)
     }
+
+    fn params(&self) -> Vec<f64> {
+        vec![self.modulation]
+    }
+
+    fn set_param(&mut self, index: usize, value: f64) {
+        if index == 0 {
+            self.modulation = value;
+        }
+    }
 }
 
 #[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
@@ -129,11 +139,11 @@ impl Node for SineWave {
     }
 
     fn input_slot_types(&self) -> &[(&'static str, SlotType)] {
-        &[("freq", SlotType::Float)]
+        &[("freq", SlotType::Pitch)]
     }
 
     fn output_slot_types(&self) -> &[(&'static str, SlotType)] {
-        &[("freq_out", SlotType::Float), ("out", SlotType::Float)]
+        &[("freq_out", SlotType::Pitch), ("out", SlotType::Audio)]
     }
 
     fn display_out(&self) -> &Option<&str> {
@@ -141,7 +151,7 @@ impl Node for SineWave {
     }
 
     fn run(
-        &self,
+        &mut self,
         ctx: &NodeCtx,
         input: HashMap<String, SlotValue>,
     ) -> Vec<(&'static str, SlotValue)> {
@@ -156,6 +166,16 @@ impl Node for SineWave {
     fn ui(&mut self, ui: &mut Ui) -> bool {
         WaveGenerator::ui(self, ui)
     }
+
+    fn params(&self) -> Vec<f64> {
+        vec![self.modulation]
+    }
+
+    fn set_param(&mut self, index: usize, value: f64) {
+        if index == 0 {
+            self.modulation = value;
+        }
+    }
 }
 
 #[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
@@ -198,11 +218,11 @@ impl Node for SawWave {
     }
 
     fn input_slot_types(&self) -> &[(&'static str, SlotType)] {
-        &[("freq", SlotType::Float)]
+        &[("freq", SlotType::Pitch)]
     }
 
     fn output_slot_types(&self) -> &[(&'static str, SlotType)] {
-        &[("freq_out", SlotType::Float), ("out", SlotType::Float)]
+        &[("freq_out", SlotType::Pitch), ("out", SlotType::Audio)]
     }
 
     fn display_out(&self) -> &Option<&str> {
@@ -210,7 +230,7 @@ impl Node for SawWave {
     }
 
     fn run(
-        &self,
+        &mut self,
         ctx: &NodeCtx,
         input: HashMap<String, SlotValue>,
     ) -> Vec<(&'static str, SlotValue)> {
@@ -225,6 +245,16 @@ impl Node for SawWave {
     fn ui(&mut self, ui: &mut Ui) -> bool {
         WaveGenerator::ui(self, ui)
     }
+
+    fn params(&self) -> Vec<f64> {
+        vec![self.modulation]
+    }
+
+    fn set_param(&mut self, index: usize, value: f64) {
+        if index == 0 {
+            self.modulation = value;
+        }
+    }
 }
 
 #[derive(Clone)]
@@ -0,0 +1,303 @@
+use crate::dsp::*;
+use crate::knob::knob;
+use crate::node::*;
+use eframe::egui::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Points of the drawn transfer curve, evenly spaced over -1..1.
+const CURVE_POINTS: usize = 17;
+
+/// Parameters before the ones of the curve.
+const SHAPE_PARAMS: usize = 4;
+
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum ShapeMode {
+    SoftClip,
+    HardClip,
+    Foldback,
+    Bitcrush,
+    Curve,
+}
+
+/// Distorts its input with a transfer function, at up to 8 times the sample
+/// rate to keep the added harmonics from aliasing.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct WaveshaperNode {
+    mode: ShapeMode,
+    /// Gain before shaping.
+    drive: f64,
+    /// Amount of shaped signal mixed with the input.
+    mix: f64,
+    /// Resolution of the bitcrusher.
+    bits: f64,
+    /// Samples each sample is held for, reducing the sample rate.
+    reduction: f64,
+    curve: Vec<f64>,
+    oversampling: usize,
+    #[serde(skip)]
+    oversampler: Option<Oversampler>,
+    /// Delays the dry signal by the latency of the oversampler, so mixing
+    /// doesn't comb filter.
+    #[serde(skip)]
+    dry_delay: Option<DelayLine>,
+    #[serde(skip)]
+    held: f64,
+    #[serde(skip)]
+    hold_count: f64,
+}
+
+impl WaveshaperNode {
+    pub fn new() -> Self {
+        Self {
+            mode: ShapeMode::SoftClip,
+            drive: 1.0,
+            mix: 1.0,
+            bits: 8.0,
+            reduction: 1.0,
+            curve: (0..CURVE_POINTS)
+                .map(|i| i as f64 / (CURVE_POINTS - 1) as f64 * 2.0 - 1.0)
+                .collect(),
+            oversampling: 1,
+            oversampler: Some(Oversampler::new(1)),
+            dry_delay: Some(DelayLine::new(MAX_LATENCY + 2)),
+            held: 0.0,
+            hold_count: 0.0,
+        }
+    }
+
+    fn shape(&self, x: f64) -> f64 {
+        match self.mode {
+            ShapeMode::SoftClip => x.tanh(),
+            ShapeMode::HardClip => x.max(-1.0).min(1.0),
+            // reflects off -1 and 1 until it is between them
+            ShapeMode::Foldback => {
+                let x = (x + 1.0).rem_euclid(4.0);
+
+                (if x > 2.0 { 4.0 - x } else { x }) - 1.0
+            }
+            ShapeMode::Bitcrush => {
+                let levels = 2.0f64.powf(self.bits.round().max(1.0) - 1.0);
+
+                (x * levels).round() / levels
+            }
+            // a loaded curve may not have `CURVE_POINTS` points until it is
+            // resized on the UI thread
+            ShapeMode::Curve if self.curve.len() < 2 => x.max(-1.0).min(1.0),
+            ShapeMode::Curve => {
+                let segments = self.curve.len() - 1;
+                let position = (x.max(-1.0).min(1.0) + 1.0) * 0.5 * segments as f64;
+                let index = (position.floor() as usize).min(segments - 1);
+                let t = position - index as f64;
+
+                self.curve[index] + (self.curve[index + 1] - self.curve[index]) * t
+            }
+        }
+    }
+
+    /// The oversampling factor, limited to what the buffers hold.
+    fn factor(&self) -> usize {
+        self.oversampling.max(1).min(MAX_OVERSAMPLING)
+    }
+
+    fn curve_ui(&mut self, ui: &mut Ui) {
+        let (rect, response) =
+            ui.allocate_exact_size(Vec2::new(150.0, 100.0), Sense::click_and_drag());
+
+        // the point closest to the pointer follows it
+        if response.dragged() || response.clicked() {
+            if let Some(pointer) = ui.input().pointer.interact_pos() {
+                let x = ((pointer.x - rect.left()) / rect.width()).max(0.0).min(1.0);
+                let y = 1.0 - ((pointer.y - rect.top()) / rect.height()).max(0.0).min(1.0);
+
+                let index = (x * (CURVE_POINTS - 1) as f32).round() as usize;
+                self.curve[index] = y as f64 * 2.0 - 1.0;
+            }
+        }
+
+        let visuals = ui.style().interact(&response);
+        let painter = ui.painter();
+
+        painter.rect(rect, 0.0, visuals.bg_fill, visuals.bg_stroke);
+
+        let points: Vec<Pos2> = self
+            .curve
+            .iter()
+            .enumerate()
+            .map(|(i, y)| {
+                Pos2::new(
+                    rect.left() + i as f32 / (CURVE_POINTS - 1) as f32 * rect.width(),
+                    rect.center().y - *y as f32 * rect.height() * 0.5,
+                )
+            })
+            .collect();
+
+        for pair in points.windows(2) {
+            painter.line_segment([pair[0], pair[1]], visuals.fg_stroke);
+        }
+    }
+}
+
+impl Node for WaveshaperNode {
+    fn name(&self) -> &str {
+        "Waveshaper"
+    }
+
+    fn input_slot_types(&self) -> &[(&'static str, SlotType)] {
+        &[("in", SlotType::Audio)]
+    }
+
+    fn output_slot_types(&self) -> &[(&'static str, SlotType)] {
+        &[("out", SlotType::Audio)]
+    }
+
+    fn display_out(&self) -> &Option<&str> {
+        &Some("out")
+    }
+
+    fn setup(&mut self) {
+        self.load_resources();
+        self.held = 0.0;
+        self.hold_count = 0.0;
+    }
+
+    fn run(
+        &mut self,
+        _ctx: &NodeCtx,
+        input: HashMap<String, SlotValue>,
+    ) -> Vec<(&'static str, SlotValue)> {
+        let dry = match input["in"] {
+            SlotValue::None => return vec![("out", SlotValue::None)],
+            value => value.unwrap_f64(0.0),
+        };
+
+        // created on the UI thread, unless the node was loaded without it
+        let factor = self.factor();
+        let mut oversampler = match self.oversampler.take() {
+            Some(oversampler) if oversampler.factor() == factor => oversampler,
+            _ => Oversampler::new(factor),
+        };
+
+        let mut buffer = [0.0; MAX_OVERSAMPLING];
+        let buffer = &mut buffer[..factor];
+
+        oversampler.upsample(dry * self.drive, buffer);
+
+        for sample in buffer.iter_mut() {
+            *sample = self.shape(*sample);
+        }
+
+        let wet = oversampler.downsample(buffer);
+        let latency = oversampler.latency();
+        self.oversampler = Some(oversampler);
+
+        let dry = if latency > 0.0 {
+            let line = self
+                .dry_delay
+                .get_or_insert_with(|| DelayLine::new(MAX_LATENCY + 2));
+
+            line.write(dry);
+            line.read(latency + 1.0)
+        } else {
+            dry
+        };
+
+        // sample rate reduction aliases on purpose, so it runs at the normal rate
+        self.hold_count -= 1.0;
+
+        if self.hold_count <= 0.0 {
+            self.held = wet;
+            self.hold_count += self.reduction.max(1.0);
+        }
+
+        let out = dry + (self.held - dry) * self.mix;
+
+        vec![("out", SlotValue::Float(out))]
+    }
+
+    fn ui(&mut self, ui: &mut Ui) -> bool {
+        let prev_mode = self.mode;
+        let prev_oversampling = self.oversampling;
+
+        ui.vertical(|ui| {
+            ui.horizontal(|ui| {
+                ui.radio_value(&mut self.mode, ShapeMode::SoftClip, "Soft");
+                ui.radio_value(&mut self.mode, ShapeMode::HardClip, "Hard");
+                ui.radio_value(&mut self.mode, ShapeMode::Foldback, "Fold");
+                ui.radio_value(&mut self.mode, ShapeMode::Bitcrush, "Crush");
+                ui.radio_value(&mut self.mode, ShapeMode::Curve, "Curve");
+            });
+
+            ui.horizontal(|ui| {
+                ui.vertical(|ui| {
+                    ui.label("Drive");
+                    knob(ui, &mut self.drive, 0.0, 20.0);
+                });
+
+                ui.vertical(|ui| {
+                    ui.label("Mix");
+                    knob(ui, &mut self.mix, 0.0, 1.0);
+                });
+
+                ui.vertical(|ui| {
+                    ui.label("Rate");
+                    knob(ui, &mut self.reduction, 1.0, 32.0);
+                });
+
+                if self.mode == ShapeMode::Bitcrush {
+                    ui.vertical(|ui| {
+                        ui.label("Bits");
+                        knob(ui, &mut self.bits, 1.0, 16.0);
+                    });
+                }
+            });
+
+            if self.mode == ShapeMode::Curve {
+                self.curve_ui(ui);
+            }
+
+            ui.horizontal(|ui| {
+                ui.label("Oversampling");
+
+                for factor in &OVERSAMPLING {
+                    ui.radio_value(&mut self.oversampling, *factor, format!("{}x", factor));
+                }
+            });
+        });
+
+        if self.oversampling != prev_oversampling {
+            self.load_resources();
+        }
+
+        self.mode != prev_mode || self.oversampling != prev_oversampling
+    }
+
+    fn params(&self) -> Vec<f64> {
+        let mut params = vec![self.drive, self.mix, self.bits, self.reduction];
+        params.extend(&self.curve);
+
+        params
+    }
+
+    fn set_param(&mut self, index: usize, value: f64) {
+        match index {
+            0 => self.drive = value,
+            1 => self.mix = value,
+            2 => self.bits = value,
+            3 => self.reduction = value,
+            _ => {
+                if let Some(point) = self.curve.get_mut(index - SHAPE_PARAMS) {
+                    *point = value;
+                }
+            }
+        }
+    }
+
+    /// Creates the filters for the oversampling factor and gives the curve
+    /// the points the editor expects.
+    fn load_resources(&mut self) {
+        self.oversampler = Some(Oversampler::new(self.factor()));
+        self.dry_delay = Some(DelayLine::new(MAX_LATENCY + 2));
+        self.curve.resize(CURVE_POINTS, 0.0);
+    }
+}